@@ -0,0 +1,47 @@
+//! Compare recording batches of samples with `add_many` against calling `add` per sample.
+
+#[macro_use]
+extern crate criterion;
+extern crate histogram;
+
+use criterion::{black_box, Criterion};
+use histogram::Histogram;
+
+/// Samples spread evenly across the magnitudes of an exponential histogram up to 70000.
+fn samples() -> Vec<u32> {
+    (0..10_000u32)
+        .map(|i| {
+            let fraction = f64::from(i.wrapping_mul(2_654_435_761)) / f64::from(u32::MAX);
+            70_000f64.powf(fraction) as u32
+        })
+        .collect()
+}
+
+fn compare(c: &mut Criterion, name: &str, mut batched: Histogram<Box<[u32]>>) {
+    let layout = batched.layout();
+    let mut single =
+        Histogram::exponential(layout.min(), layout.max(), layout.bucket_count() as u32);
+    let samples = samples();
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("add", |b| {
+        b.iter(|| {
+            for &sample in &samples {
+                single.add(black_box(sample));
+            }
+        })
+    });
+    group.bench_function("add_many", |b| {
+        b.iter(|| batched.add_many(black_box(&samples)))
+    });
+    group.finish();
+}
+
+fn add_many(c: &mut Criterion) {
+    compare(c, "exponential_10", Histogram::exponential(1, 60000, 10));
+    compare(c, "exponential_32", Histogram::exponential(1, 60000, 32));
+    compare(c, "exponential_100", Histogram::exponential(1, 60000, 100));
+}
+
+criterion_group!(benches, add_many);
+criterion_main!(benches);
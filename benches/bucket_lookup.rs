@@ -0,0 +1,99 @@
+//! Compare recording with the computed bucket lookup of linear histograms
+//! against searching the same ranges,
+//! and searching exponential ranges against estimating the bucket from logarithms.
+
+#[macro_use]
+extern crate criterion;
+extern crate histogram;
+
+use criterion::{black_box, Criterion};
+use histogram::Histogram;
+
+fn samples() -> Vec<u32> {
+    (0..1000u32)
+        .map(|i| i.wrapping_mul(2_654_435_761) % 70_000)
+        .collect()
+}
+
+fn compare(c: &mut Criterion, name: &str, mut direct: Histogram<Box<[u32]>>) {
+    let layout = direct.layout();
+    let ranges = layout.ranges().to_vec().into_boxed_slice();
+    let mut searched = Histogram::factory_get(layout.min(), layout.max(), ranges);
+    let samples = samples();
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("direct", |b| {
+        b.iter(|| {
+            for &sample in &samples {
+                direct.add(black_box(sample));
+            }
+        })
+    });
+    group.bench_function("search", |b| {
+        b.iter(|| {
+            for &sample in &samples {
+                searched.add(black_box(sample));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Estimate the bucket of `value` from logarithms, then step to the right one.
+fn estimate_exponential(ranges: &[u32], min: u32, max: u32, value: u32) -> usize {
+    if value < min {
+        return 0;
+    }
+    let log_min = f64::from(min).ln();
+    let offset = (f64::from(value).ln() - log_min) * (ranges.len() - 2) as f64
+        / (f64::from(max).ln() - log_min);
+    let mut idx = std::cmp::min(1 + offset as usize, ranges.len() - 1);
+    while ranges[idx] > value {
+        idx -= 1;
+    }
+    while idx + 1 < ranges.len() && ranges[idx + 1] <= value {
+        idx += 1;
+    }
+    idx
+}
+
+fn compare_exponential(c: &mut Criterion, name: &str, mut direct: Histogram<Box<[u32]>>) {
+    let layout = direct.layout();
+    let ranges = layout.ranges().to_vec();
+    let (min, max) = (layout.min(), layout.max());
+    let mut buckets = vec![0u64; ranges.len()];
+    let samples = samples();
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("search", |b| {
+        b.iter(|| {
+            for &sample in &samples {
+                direct.add(black_box(sample));
+            }
+        })
+    });
+    group.bench_function("logarithm", |b| {
+        b.iter(|| {
+            for &sample in &samples {
+                buckets[estimate_exponential(&ranges, min, max, black_box(sample))] += 1;
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bucket_lookup(c: &mut Criterion) {
+    compare(c, "linear_10", Histogram::linear(1, 60000, 10));
+    compare(c, "linear_100", Histogram::linear(1, 60000, 100));
+    compare(c, "linear_1000", Histogram::linear(1, 60000, 1000));
+    compare_exponential(c, "exponential_10", Histogram::exponential(1, 60000, 10));
+    compare_exponential(c, "exponential_100", Histogram::exponential(1, 60000, 100));
+    compare_exponential(
+        c,
+        "exponential_1000",
+        Histogram::exponential(1, 60000, 1000),
+    );
+}
+
+criterion_group!(benches, bucket_lookup);
+criterion_main!(benches);
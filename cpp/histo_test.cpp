@@ -16,6 +16,15 @@ struct StaticHistogram final {
 
   inline void Add(unsigned int sample) { histogram_add(this, sample); }
 
+  inline void AddMany(const unsigned int* samples, size_t len) {
+    histogram_add_many(this, samples, len);
+  }
+
+  inline void AccumulateMany(const unsigned int* values,
+                             const unsigned int* counts, size_t len) {
+    histogram_accumulate_many(this, values, counts, len);
+  }
+
   inline const char* Serialize() { return histogram_serialize(this); }
 
   inline const char* Persist() { return histogram_serialize_persist(this); }
@@ -25,11 +34,21 @@ struct StaticHistogram final {
   inline int ranges(int idx) const { return histogram_ranges(this, idx); }
 
   inline bool is_empty() const { return histogram_is_empty(this); }
+  inline uint64_t count() const { return histogram_count(this); }
+  inline uint64_t sum() const { return histogram_sum(this); }
 
   inline Snapshot* snapshot() const {
     return histogram_snapshot(this);
   }
 
+  inline Snapshot* snapshot_and_clear() {
+    return histogram_snapshot_and_clear(this);
+  }
+
+  inline Snapshot* delta_since(const Snapshot* aSnapshot) const {
+    return histogram_delta_since(this, aSnapshot);
+  }
+
  private:
   StaticHistogram() = delete;
   StaticHistogram(const StaticHistogram&) = delete;
@@ -43,11 +62,11 @@ struct Snapshot final {
     histogram_snapshot_free(reinterpret_cast<Snapshot*>(aSnapshot));
   }
 
-  inline size_t counts(int idx) {
+  inline uint64_t counts(int idx) {
     return histogram_snapshot_counts(this, idx);
   }
 
-  inline size_t sum() {
+  inline uint64_t sum() {
     return histogram_snapshot_sum(this);
   }
 
@@ -86,6 +105,13 @@ int main(void) {
   Snapshot *snap = h->snapshot();
   std::cout << "Snapshot: " << snap->sum() << std::endl;
 
+  h->Add(100);
+  Snapshot *delta = h->delta_since(snap);
+  std::cout << "Delta:    " << delta->sum() << std::endl;
+
+  delete delta;
+  delete snap;
+
   delete h;
 
   return 0;
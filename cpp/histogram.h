@@ -17,16 +17,39 @@ struct StaticHistogram;
 
 extern "C" {
 
+// Add `counts[i]` number of `values[i]` to the given histogram, for each of the `len` values.
+void histogram_accumulate_many(StaticHistogram *histogram,
+                               const unsigned int *values,
+                               const unsigned int *counts,
+                               uintptr_t len);
+
 // Add a single value to the given histogram.
 void histogram_add(StaticHistogram *histogram, unsigned int sample);
 
+// Add `len` values from `samples` to the given histogram.
+void histogram_add_many(StaticHistogram *histogram, const unsigned int *samples, uintptr_t len);
+
 // Get the number of buckets in this histogram.
 uintptr_t histogram_bucket_count(const StaticHistogram *histogram);
 
 // Clear the stored data in the histogram
 void histogram_clear(StaticHistogram *histogram);
 
+// Get the total count of values recorded in this histogram.
+uint64_t histogram_count(const StaticHistogram *histogram);
+
+// Get the data recorded in the histogram since `snapshot` was taken, leaving the histogram as is.
+//
+// Returns null if the snapshot was not taken from this histogram
+// or the histogram was cleared since.
+// The returned snapshot should be passed back to `histogram_snapshot_free` to deallocate after
+// usage.
+Snapshot *histogram_delta_since(const StaticHistogram *histogram, const Snapshot *snapshot);
+
 // Create a new histogram from an external array of ranges.
+//
+// `ranges` holds the start of each of the `bucket_count` buckets, followed by `INT_MAX`.
+// Returns a null pointer if the ranges are invalid.
 StaticHistogram *histogram_factory_get(unsigned int min,
                                        unsigned int max,
                                        uintptr_t bucket_count,
@@ -57,11 +80,20 @@ char *histogram_serialize_persist(StaticHistogram *histogram);
 
 Snapshot *histogram_snapshot(const StaticHistogram *histogram);
 
-uint32_t histogram_snapshot_counts(const Snapshot *snapshot, int idx);
+// Get a copy of the data recorded in the histogram and clear it.
+//
+// The returned snapshot should be passed back to `histogram_snapshot_free` to deallocate after
+// usage.
+Snapshot *histogram_snapshot_and_clear(StaticHistogram *histogram);
+
+uint64_t histogram_snapshot_counts(const Snapshot *snapshot, int idx);
 
 void histogram_snapshot_free(Snapshot *snapshot);
 
-uint32_t histogram_snapshot_sum(const Snapshot *snapshot);
+uint64_t histogram_snapshot_sum(const Snapshot *snapshot);
+
+// Get the total sum of values recorded in this histogram.
+uint64_t histogram_sum(const StaticHistogram *histogram);
 
 } // extern "C"
 
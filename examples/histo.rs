@@ -52,10 +52,7 @@ fn try_main() -> io::Result<()> {
             if line.is_empty() {
                 continue;
             }
-            let sample: u32 = line
-                .trim()
-                .parse()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let sample: u32 = line.trim().parse().map_err(io::Error::other)?;
 
             hist.add(sample);
         }
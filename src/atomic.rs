@@ -0,0 +1,347 @@
+//! Histograms that can be recorded into concurrently.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use super::{adjust_value, find_bucket, Histogram, Metadata, Snapshot, Type};
+
+/// The bit of `AtomicHistogram::phase` selecting the half values are recorded into.
+const HOT_BIT: u64 = 1 << 63;
+
+/// A histogram that can be recorded into from multiple threads without locking.
+///
+/// It uses the same layout as a `Histogram` and is created from one:
+///
+/// ```rust
+/// # use histogram::{AtomicHistogram, Histogram};
+/// let hist = AtomicHistogram::from(Histogram::exponential(1, 500, 10));
+/// hist.add(14);
+/// assert_eq!(1, hist.count());
+/// ```
+#[derive(Debug)]
+pub struct AtomicHistogram<T: AsRef<[u32]>> {
+    min: u32,
+    max: u32,
+    ranges: T,
+    /// Values are recorded into the hot half, while `snapshot_and_clear` drains the other one.
+    halves: [Half; 2],
+    /// The hot half in `HOT_BIT`, and the number of writers that started recording into it.
+    phase: AtomicU64,
+    /// The number of writers that finished recording into each half.
+    finished: [AtomicU64; 2],
+    /// Held while switching and draining the halves.
+    drain: Mutex<()>,
+
+    typ: Type,
+    bits: u32,
+    meta: Option<Box<Metadata>>,
+}
+
+#[derive(Debug)]
+struct Half {
+    buckets: Box<[AtomicU64]>,
+    sum: AtomicU64,
+}
+
+impl Half {
+    fn new(counts: &[u64], sum: u64) -> Half {
+        Half {
+            buckets: counts
+                .iter()
+                .map(|&count| AtomicU64::new(count))
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+            sum: AtomicU64::new(sum),
+        }
+    }
+}
+
+impl<T: AsRef<[u32]>> From<Histogram<T>> for AtomicHistogram<T> {
+    fn from(histogram: Histogram<T>) -> AtomicHistogram<T> {
+        let empty = vec![0; histogram.buckets.len()];
+
+        AtomicHistogram {
+            min: histogram.min,
+            max: histogram.max,
+            ranges: histogram.ranges,
+            halves: [
+                Half::new(&histogram.buckets, histogram.sum),
+                Half::new(&empty, 0),
+            ],
+            phase: AtomicU64::new(0),
+            finished: [AtomicU64::new(0), AtomicU64::new(0)],
+            drain: Mutex::new(()),
+            typ: histogram.typ,
+            bits: histogram.bits,
+            meta: histogram.meta,
+        }
+    }
+}
+
+impl<T: AsRef<[u32]>> AtomicHistogram<T> {
+    /// Get the number of buckets in this histogram.
+    pub fn bucket_count(&self) -> usize {
+        self.halves[0].buckets.len()
+    }
+
+    fn ranges(&self) -> &[u32] {
+        &self.ranges.as_ref()[..self.bucket_count()]
+    }
+
+    /// Add a single value to this histogram.
+    pub fn add(&self, value: u32) {
+        self.accumulate(adjust_value(self.typ, value), 1);
+    }
+
+    /// Add `count` number of values.
+    ///
+    /// Count histograms ignore the value and increase their counter by `count`.
+    pub fn accumulate(&self, value: u32, count: u32) {
+        let (idx, sum) = if self.typ == Type::Count {
+            (0, u64::from(count))
+        } else {
+            (
+                find_bucket(
+                    self.typ,
+                    self.min,
+                    self.max,
+                    self.bits,
+                    self.ranges(),
+                    value,
+                ),
+                u64::from(value) * u64::from(count),
+            )
+        };
+
+        let hot = (self.phase.fetch_add(1, Ordering::SeqCst) >> 63) as usize;
+        let half = &self.halves[hot];
+        half.buckets[idx].fetch_add(u64::from(count), Ordering::Relaxed);
+        half.sum.fetch_add(sum, Ordering::Relaxed);
+        self.finished[hot].fetch_add(1, Ordering::Release);
+    }
+
+    /// Get the total sum of values recorded in this histogram.
+    pub fn sum(&self) -> u64 {
+        self.halves
+            .iter()
+            .map(|half| half.sum.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Get the total count of values recorded in this histogram.
+    pub fn count(&self) -> u64 {
+        self.counts().iter().sum()
+    }
+
+    /// Check if this histogram recorded any values.
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    fn counts(&self) -> Box<[u64]> {
+        (0..self.bucket_count())
+            .map(|idx| {
+                self.halves
+                    .iter()
+                    .map(|half| half.buckets[idx].load(Ordering::Relaxed))
+                    .sum()
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
+    }
+
+    /// Get a copy of the data currently recorded in this histogram.
+    ///
+    /// Values recorded concurrently might be missing from the snapshot,
+    /// or be counted in a bucket without being part of the sum yet.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ranges: self.ranges().into(),
+            counts: self.counts(),
+            sum: self.sum(),
+        }
+    }
+
+    /// Get a copy of the data currently recorded in this histogram and clear it.
+    ///
+    /// Every value added concurrently is part of exactly one snapshot,
+    /// with both its count and its contribution to the sum.
+    /// Recording never waits, but this waits for values being recorded while it's called.
+    pub fn snapshot_and_clear(&self) -> Snapshot {
+        let _drain = self.drain.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Move writers to the other half, then wait for those still recording into the old one.
+        let hot = self.phase.load(Ordering::SeqCst) & HOT_BIT;
+        let old = self.phase.swap(hot ^ HOT_BIT, Ordering::SeqCst);
+        let cold = (old >> 63) as usize;
+        let started = old & !HOT_BIT;
+        while self.finished[cold].load(Ordering::Acquire) != started {
+            thread::yield_now();
+        }
+
+        let half = &self.halves[cold];
+        let counts = half
+            .buckets
+            .iter()
+            .map(|bucket| bucket.swap(0, Ordering::Relaxed))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        let sum = half.sum.swap(0, Ordering::Relaxed);
+        self.finished[cold].store(0, Ordering::Relaxed);
+
+        Snapshot {
+            ranges: self.ranges().into(),
+            counts,
+            sum,
+        }
+    }
+
+    /// Get a plain histogram with the data currently recorded in this histogram.
+    ///
+    /// The same caveats as for `snapshot` apply to values recorded concurrently.
+    pub fn to_histogram(&self) -> Histogram<T>
+    where
+        T: Clone,
+    {
+        let buckets = self.counts();
+        Histogram {
+            min: self.min,
+            max: self.max,
+            ranges: self.ranges.clone(),
+            count: buckets.iter().sum(),
+            buckets,
+            sum: self.sum(),
+            typ: self.typ,
+            bits: self.bits,
+            meta: self.meta.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn concurrent_add() {
+        let atomic = Arc::new(AtomicHistogram::from(Histogram::exponential(1, 500, 10)));
+        let mut expected = Histogram::exponential(1, 500, 10);
+
+        let threads = (0..4)
+            .map(|_| {
+                let atomic = Arc::clone(&atomic);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        atomic.add(i % 600);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..4 {
+            for i in 0..1000 {
+                expected.add(i % 600);
+            }
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(4000, atomic.count());
+        assert_eq!(expected.sum(), atomic.sum());
+
+        let histogram = atomic.to_histogram();
+        assert_eq!(expected.buckets, histogram.buckets);
+        assert_eq!(expected.count(), histogram.count());
+        assert_eq!(
+            serde_json::to_string(&expected).unwrap(),
+            serde_json::to_string(&histogram).unwrap()
+        );
+
+        let snapshot = atomic.snapshot();
+        assert_eq!(expected.buckets, snapshot.counts);
+        assert_eq!(expected.sum(), snapshot.sum);
+    }
+
+    #[test]
+    fn concurrent_snapshot_and_clear() {
+        let atomic = Arc::new(AtomicHistogram::from(Histogram::linear(1, 100, 10)));
+        // Only record bucket starts, so each snapshot's sum follows from its counts.
+        let starts = atomic.ranges()[1..].to_vec();
+
+        let threads = (0..4)
+            .map(|_| {
+                let atomic = Arc::clone(&atomic);
+                let starts = starts.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        atomic.add(starts[i % starts.len()]);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut snapshots = vec![];
+        while threads.iter().any(|t| !t.is_finished()) {
+            snapshots.push(atomic.snapshot_and_clear());
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        snapshots.push(atomic.snapshot_and_clear());
+        assert!(atomic.is_empty());
+
+        let mut count = 0;
+        let mut sum = 0;
+        for snapshot in &snapshots {
+            let expected_sum = snapshot
+                .buckets()
+                .map(|b| u64::from(b.start()) * b.count())
+                .sum::<u64>();
+            assert_eq!(expected_sum, snapshot.sum(), "{:?}", snapshot);
+            count += snapshot.count();
+            sum += snapshot.sum();
+        }
+
+        let per_thread = (0..10_000)
+            .map(|i| u64::from(starts[i % starts.len()]))
+            .sum::<u64>();
+        assert_eq!(40_000, count);
+        assert_eq!(4 * per_thread, sum);
+    }
+
+    #[test]
+    fn snapshot_and_clear_initial_data() {
+        let mut hist = Histogram::exponential(1, 500, 10);
+        hist.accumulate(14, 3);
+        let atomic = AtomicHistogram::from(hist);
+        atomic.add(300);
+        assert_eq!(4, atomic.count());
+
+        let snapshot = atomic.snapshot_and_clear();
+        assert_eq!(4, snapshot.count());
+        assert_eq!(342, snapshot.sum());
+        assert!(atomic.is_empty());
+
+        atomic.add(7);
+        atomic.add(8);
+        assert_eq!(2, atomic.count());
+        assert_eq!(15, atomic.snapshot().sum());
+        assert_eq!(15, atomic.snapshot_and_clear().sum());
+        assert_eq!(0, atomic.snapshot_and_clear().count());
+    }
+
+    #[test]
+    fn log_linear() {
+        let atomic = AtomicHistogram::from(Histogram::log_linear(2));
+        let mut expected = Histogram::log_linear(2);
+        for &value in &[3, 700, 123_456, u32::MAX] {
+            atomic.add(value);
+            expected.add(value);
+        }
+        assert_eq!(expected.snapshot(), atomic.snapshot());
+        assert_eq!(expected.snapshot(), atomic.snapshot_and_clear());
+    }
+}
@@ -0,0 +1,266 @@
+//! Configure and create histograms of any type in one place.
+
+use super::{BucketLayout, Histogram, HistogramError, Metadata, Type};
+
+/// A builder for histograms of any type.
+///
+/// ```rust
+/// # use histogram::{HistogramBuilder, Type};
+/// let hist = HistogramBuilder::new()
+///     .kind(Type::Exponential)
+///     .range(1, 500)
+///     .buckets(10)
+///     .name("page_load")
+///     .unit("ms")
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(10, hist.bucket_count());
+/// assert_eq!(Some("ms"), hist.unit());
+/// ```
+///
+/// Enumerated histograms of `n` values are linear histograms with a range of `1..n`
+/// and `n + 1` buckets.
+#[derive(Debug, Clone, Default)]
+pub struct HistogramBuilder {
+    kind: Option<Type>,
+    range: Option<(u32, u32)>,
+    buckets: Option<u32>,
+    ranges: Option<Vec<u32>>,
+    significant_digits: Option<u32>,
+    name: Option<String>,
+    unit: Option<String>,
+}
+
+impl HistogramBuilder {
+    /// Create a builder without any parameters set.
+    pub fn new() -> HistogramBuilder {
+        HistogramBuilder::default()
+    }
+
+    /// Set the type of the histogram.
+    ///
+    /// Defaults to `External` if custom ranges are given, `Exponential` otherwise.
+    /// Boolean, flag and count histograms have a fixed layout and ignore the range and buckets.
+    /// Log-linear histograms are laid out by their significant digits instead.
+    pub fn kind(mut self, kind: Type) -> HistogramBuilder {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Set the range of the histogram.
+    pub fn range(mut self, min: u32, max: u32) -> HistogramBuilder {
+        self.range = Some((min, max));
+        self
+    }
+
+    /// Set the number of buckets.
+    pub fn buckets(mut self, count: u32) -> HistogramBuilder {
+        self.buckets = Some(count);
+        self
+    }
+
+    /// Use custom bucket ranges, holding the start of each bucket.
+    ///
+    /// Only valid for `External` histograms.
+    pub fn ranges(mut self, ranges: Vec<u32>) -> HistogramBuilder {
+        self.ranges = Some(ranges);
+        self
+    }
+
+    /// Set the number of significant digits kept apart by the buckets.
+    ///
+    /// Only valid for `LogLinear` histograms.
+    pub fn significant_digits(mut self, digits: u32) -> HistogramBuilder {
+        self.significant_digits = Some(digits);
+        self
+    }
+
+    /// Set the name of the histogram.
+    pub fn name<S: Into<String>>(mut self, name: S) -> HistogramBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the unit of the values recorded in the histogram.
+    pub fn unit<S: Into<String>>(mut self, unit: S) -> HistogramBuilder {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    /// Create the histogram.
+    ///
+    /// Fails if required parameters are missing or the parameters are invalid for the type.
+    pub fn build(self) -> Result<Histogram<Box<[u32]>>, HistogramError> {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None if self.ranges.is_some() => Type::External,
+            None => Type::Exponential,
+        };
+
+        if kind != Type::LogLinear && self.significant_digits.is_some() {
+            return Err(HistogramError::InvalidBuilder {
+                reason: "significant digits are only valid for log-linear histograms",
+            });
+        }
+
+        let mut hist = match kind {
+            Type::Boolean => Histogram::boolean(),
+            Type::Flag => Histogram::flag(),
+            Type::Count => Histogram::count_histogram(),
+            Type::Sparse => {
+                return Err(HistogramError::InvalidBuilder {
+                    reason: "sparse histograms are created as a SparseHistogram",
+                })
+            }
+            Type::LogLinear => {
+                let digits = self
+                    .significant_digits
+                    .ok_or(HistogramError::InvalidBuilder {
+                        reason: "missing number of significant digits",
+                    })?;
+                Histogram::try_log_linear(digits)?
+            }
+            Type::External => {
+                let ranges = self.ranges.ok_or(HistogramError::InvalidBuilder {
+                    reason: "external histograms need custom ranges",
+                })?;
+                let (min, max) = self.range.ok_or(HistogramError::InvalidBuilder {
+                    reason: "missing range",
+                })?;
+                if let Some(count) = self.buckets {
+                    if count as usize != ranges.len() {
+                        return Err(HistogramError::BucketCountMismatch {
+                            expected: count as usize,
+                            actual: ranges.len(),
+                        });
+                    }
+                }
+                Histogram::try_from_ranges(min, max, ranges.into_boxed_slice())?
+            }
+            Type::Exponential | Type::Linear | Type::Categorical => {
+                if self.ranges.is_some() {
+                    return Err(HistogramError::InvalidBuilder {
+                        reason: "custom ranges are only valid for external histograms",
+                    });
+                }
+                let (min, max) = self.range.ok_or(HistogramError::InvalidBuilder {
+                    reason: "missing range",
+                })?;
+                let count = self.buckets.ok_or(HistogramError::InvalidBuilder {
+                    reason: "missing number of buckets",
+                })?;
+
+                if kind == Type::Exponential {
+                    Histogram::try_exponential(min, max, count)?
+                } else {
+                    let mut hist = Histogram::try_linear(min, max, count)?;
+                    hist.typ = kind;
+                    hist
+                }
+            }
+        };
+
+        if self.name.is_some() || self.unit.is_some() {
+            hist.meta = Some(Box::new(Metadata {
+                name: self.name,
+                unit: self.unit,
+            }));
+        }
+
+        Ok(hist)
+    }
+
+    /// Create a layout that can be shared by many histograms.
+    ///
+    /// Name and unit are not part of the layout.
+    pub fn build_layout(self) -> Result<BucketLayout, HistogramError> {
+        self.build().map(|hist| hist.layout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn build() {
+        let mut hist = HistogramBuilder::new()
+            .kind(Type::Linear)
+            .range(1, 500)
+            .buckets(10)
+            .name("latency")
+            .unit("ms")
+            .build()
+            .unwrap();
+        hist.add(14);
+
+        assert_eq!(Type::Linear, hist.typ);
+        assert_eq!(Some("latency"), hist.name());
+        assert_eq!(Some("ms"), hist.unit());
+
+        let display = hist.to_string();
+        assert!(
+            display.starts_with("# Name = latency\n# Unit = ms\n# Number of samples = 1\n"),
+            "{}",
+            display
+        );
+
+        let serialized = serde_json::to_string(&hist).unwrap();
+        assert!(
+            serialized.starts_with(r#"{"name":"latency","unit":"ms","range":[1,500]"#),
+            "{}",
+            serialized
+        );
+        let restored: Histogram<Box<[u32]>> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(Some("latency"), restored.name());
+        assert_eq!(Some("ms"), restored.unit());
+    }
+
+    #[test]
+    fn build_external() {
+        let hist = HistogramBuilder::new()
+            .range(1, 10)
+            .ranges(vec![0, 1, 5, 10])
+            .build()
+            .unwrap();
+        assert_eq!(Type::External, hist.typ);
+        assert_eq!(4, hist.bucket_count());
+        assert_eq!(None, hist.name());
+    }
+
+    #[test]
+    fn build_invalid() {
+        match HistogramBuilder::new().range(1, 500).build() {
+            Err(HistogramError::InvalidBuilder { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match HistogramBuilder::new()
+            .kind(Type::Linear)
+            .range(1, 500)
+            .ranges(vec![0, 1, 2])
+            .build()
+        {
+            Err(HistogramError::InvalidBuilder { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match HistogramBuilder::new().range(1, 500).buckets(2).build() {
+            Err(HistogramError::InvalidBucketCount { count: 2 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match HistogramBuilder::new().kind(Type::LogLinear).build() {
+            Err(HistogramError::InvalidBuilder { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match HistogramBuilder::new()
+            .range(1, 500)
+            .buckets(10)
+            .significant_digits(2)
+            .build()
+        {
+            Err(HistogramError::InvalidBuilder { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
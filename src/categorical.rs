@@ -0,0 +1,242 @@
+//! Categorical histograms, recording string labels.
+
+use std::fmt;
+
+use serde::ser::{Serialize, SerializeMap, SerializeStruct, Serializer};
+
+use super::{write_rows, Histogram, HistogramError, Type};
+
+/// The label of the bucket recording all unknown labels.
+pub const OTHER_LABEL: &str = "__other__";
+
+/// A histogram over a fixed set of string labels.
+///
+/// It is backed by an enumerated histogram with one bucket per label,
+/// plus a bucket for all labels not in the set.
+#[derive(Debug)]
+pub struct CategoricalHistogram {
+    labels: Box<[String]>,
+    histogram: Histogram<Box<[u32]>>,
+}
+
+impl CategoricalHistogram {
+    /// Create a categorical histogram recording the given labels.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the labels are invalid, see `try_new`.
+    pub fn new<I, S>(labels: I) -> CategoricalHistogram
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::try_new(labels).expect("invalid categorical histogram")
+    }
+
+    /// Create a categorical histogram recording the given labels.
+    ///
+    /// Fails if `labels` is empty, holds a label twice, or holds `OTHER_LABEL`.
+    pub fn try_new<I, S>(labels: I) -> Result<CategoricalHistogram, HistogramError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let labels = labels
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<String>>()
+            .into_boxed_slice();
+        if labels.is_empty() {
+            return Err(HistogramError::InvalidBucketCount { count: 1 });
+        }
+        for (idx, label) in labels.iter().enumerate() {
+            if label == OTHER_LABEL || labels[..idx].contains(label) {
+                return Err(HistogramError::InvalidLabel {
+                    name: label.clone(),
+                });
+            }
+        }
+
+        let mut histogram = Histogram::try_enumerated(labels.len() as u32 + 1)?;
+        histogram.typ = Type::Categorical;
+
+        Ok(CategoricalHistogram { labels, histogram })
+    }
+
+    /// Record a single `label`.
+    ///
+    /// Labels not known to this histogram are recorded as `OTHER_LABEL`.
+    pub fn add_label(&mut self, label: &str) {
+        let idx = self
+            .labels
+            .iter()
+            .position(|l| l == label)
+            .unwrap_or(self.labels.len());
+        self.histogram.add(idx as u32);
+    }
+
+    /// The labels known to this histogram, without `OTHER_LABEL`.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Get an iterator over this histogram's buckets, one per label.
+    ///
+    /// The last bucket is the `OTHER_LABEL` bucket.
+    pub fn buckets(&self) -> LabeledBuckets<'_> {
+        LabeledBuckets {
+            histogram: self,
+            index: 0,
+        }
+    }
+
+    /// Get the total count of labels recorded in this histogram.
+    pub fn count(&self) -> u64 {
+        self.histogram.count()
+    }
+
+    /// Clear the stored data in the histogram
+    pub fn clear(&mut self) {
+        self.histogram.clear();
+    }
+
+    /// Check if this histogram recorded any labels.
+    pub fn is_empty(&self) -> bool {
+        self.histogram.is_empty()
+    }
+}
+
+/// An iterator over the labeled buckets of a categorical histogram.
+#[derive(Debug, Clone)]
+pub struct LabeledBuckets<'a> {
+    histogram: &'a CategoricalHistogram,
+    index: usize,
+}
+
+impl<'a> Iterator for LabeledBuckets<'a> {
+    type Item = LabeledBucket<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let labels = &self.histogram.labels;
+        let label = match self.index {
+            idx if idx < labels.len() => &labels[idx],
+            idx if idx == labels.len() => OTHER_LABEL,
+            _ => return None,
+        };
+
+        let count = self.histogram.histogram.buckets[self.index];
+        self.index += 1;
+
+        Some(LabeledBucket { label, count })
+    }
+}
+
+/// A label and the number of times it was recorded.
+#[derive(Debug, Clone)]
+pub struct LabeledBucket<'a> {
+    label: &'a str,
+    count: u64,
+}
+
+impl<'a> LabeledBucket<'a> {
+    /// The label of this bucket.
+    pub fn label(&self) -> &'a str {
+        self.label
+    }
+
+    /// The number of times this label was recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl fmt::Display for CategoricalHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .buckets()
+            .map(|bucket| (bucket.label().to_string(), bucket.count()))
+            .collect::<Vec<_>>();
+        write_rows(f, self.count(), &rows)
+    }
+}
+
+/// The recorded labels and their counts, skipping labels that were never recorded.
+struct LabeledValues<'a>(&'a CategoricalHistogram);
+
+impl<'a> Serialize for LabeledValues<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for bucket in self.0.buckets().filter(|b| b.count() > 0) {
+            map.serialize_entry(bucket.label(), &bucket.count())?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for CategoricalHistogram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let histogram = &self.histogram;
+        let mut state = serializer.serialize_struct("CategoricalHistogram", 6)?;
+        state.serialize_field("range", &[histogram.min, histogram.max])?;
+        state.serialize_field("bucket_count", &histogram.bucket_count())?;
+        state.serialize_field("histogram_type", &(histogram.typ as u32))?;
+        state.serialize_field("labels", &self.labels)?;
+        state.serialize_field("values", &LabeledValues(self))?;
+        state.serialize_field("sum", &histogram.sum)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn labels() {
+        let mut h = CategoricalHistogram::new(vec!["foo", "bar"]);
+        h.add_label("foo");
+        h.add_label("bar");
+        h.add_label("bar");
+        h.add_label("baz");
+
+        assert_eq!(4, h.count());
+        let buckets = h
+            .buckets()
+            .map(|b| (b.label(), b.count()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("foo", 1), ("bar", 2), (OTHER_LABEL, 1)], buckets);
+
+        let serialized = serde_json::to_string(&h).unwrap();
+        assert_eq!(
+            r#"{"range":[1,3],"bucket_count":4,"histogram_type":5,"labels":["foo","bar"],"values":{"foo":1,"bar":2,"__other__":1},"sum":4}"#,
+            serialized
+        );
+
+        let display = h.to_string();
+        assert!(display.contains("      bar [ 2 ]: ∎∎"), "{}", display);
+        assert!(display.contains("__other__ [ 1 ]: ∎"), "{}", display);
+    }
+
+    #[test]
+    fn invalid_labels() {
+        match CategoricalHistogram::try_new(Vec::<String>::new()) {
+            Err(HistogramError::InvalidBucketCount { count: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match CategoricalHistogram::try_new(vec!["a", OTHER_LABEL]) {
+            Err(HistogramError::InvalidLabel { ref name }) if name == OTHER_LABEL => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match CategoricalHistogram::try_new(vec!["a", "b", "a"]) {
+            Err(HistogramError::InvalidLabel { ref name }) if name == "a" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Histograms weighing recent values more than older ones.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::window::{Clock, SystemClock};
+use super::{estimate_weighted_quantile, find_bucket, BucketLayout, Type};
+
+/// Number of half-lives after which the weights are rescaled to keep them from overflowing.
+const RESCALE_AFTER: f64 = 64.0;
+
+/// A histogram whose values lose half of their weight with each `half_life` that passes.
+///
+/// Instead of decaying all buckets as time passes, each new value gets a weight that doubles
+/// with every half-life since a landmark time, so adding a value only touches its bucket.
+/// Once the weights grow too large, they are scaled down and the landmark moves to the present.
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # use histogram::{DecayingHistogram, Histogram};
+/// let layout = Arc::new(Histogram::exponential(1, 10_000, 50).layout());
+/// let mut latency = DecayingHistogram::new(&layout, Duration::from_secs(60));
+///
+/// latency.add(20);
+/// latency.add(40);
+///
+/// assert!((latency.mean().unwrap() - 30.0).abs() < 0.1);
+/// ```
+#[derive(Debug)]
+pub struct DecayingHistogram<C: Clock = SystemClock> {
+    layout: Arc<BucketLayout>,
+    weights: Box<[f64]>,
+    /// The weight of all buckets, relative to the landmark.
+    total: f64,
+    /// The sum of all values multiplied by their weight, relative to the landmark.
+    weighted_sum: f64,
+
+    half_life: Duration,
+    landmark: Instant,
+    clock: C,
+}
+
+impl DecayingHistogram<SystemClock> {
+    /// Create a histogram with the buckets of `layout`, halving the weight of values every `half_life`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `half_life` is zero.
+    pub fn new(layout: &Arc<BucketLayout>, half_life: Duration) -> DecayingHistogram<SystemClock> {
+        DecayingHistogram::with_clock(layout, half_life, SystemClock)
+    }
+}
+
+impl<C: Clock> DecayingHistogram<C> {
+    /// Create a histogram with the buckets of `layout`,
+    /// halving the weight of values every `half_life` as told by `clock`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `half_life` is zero.
+    pub fn with_clock(
+        layout: &Arc<BucketLayout>,
+        half_life: Duration,
+        clock: C,
+    ) -> DecayingHistogram<C> {
+        assert!(
+            half_life > Duration::from_nanos(0),
+            "the half-life of a decaying histogram can't be zero"
+        );
+
+        let landmark = clock.now();
+        DecayingHistogram {
+            layout: Arc::clone(layout),
+            weights: vec![0.0; layout.bucket_count()].into_boxed_slice(),
+            total: 0.0,
+            weighted_sum: 0.0,
+            half_life,
+            landmark,
+            clock,
+        }
+    }
+
+    /// The layout of this histogram's buckets.
+    pub fn layout(&self) -> &Arc<BucketLayout> {
+        &self.layout
+    }
+
+    /// Add a single value to this histogram with the full weight of a new value.
+    pub fn add(&mut self, value: u32) {
+        self.accumulate(value, 1);
+    }
+
+    /// Add `count` number of values.
+    ///
+    /// Count histograms ignore the value, see `Histogram::accumulate`.
+    pub fn accumulate(&mut self, value: u32, count: u32) {
+        let mut age = self.half_lives_since_landmark();
+        if age > RESCALE_AFTER {
+            self.rescale(age);
+            age = 0.0;
+        }
+
+        let weight = f64::from(count) * age.exp2();
+        let layout = &self.layout;
+        let (idx, value) = if layout.kind() == Type::Count {
+            (0, 1)
+        } else {
+            let ranges = layout.ranges();
+            let idx = find_bucket(
+                layout.kind(),
+                layout.min(),
+                layout.max(),
+                layout.bits(),
+                ranges,
+                value,
+            );
+            (idx, value)
+        };
+
+        self.weights[idx] += weight;
+        self.total += weight;
+        self.weighted_sum += weight * f64::from(value);
+    }
+
+    /// Get the decayed weight of all values, where a new value weighs 1.
+    pub fn weight(&self) -> f64 {
+        self.total * (-self.half_lives_since_landmark()).exp2()
+    }
+
+    /// Get the mean of all values, weighted by their decayed weight.
+    ///
+    /// Returns `None` if no values were recorded.
+    pub fn mean(&self) -> Option<f64> {
+        if self.total == 0.0 {
+            return None;
+        }
+        Some(self.weighted_sum / self.total)
+    }
+
+    /// Estimate the value below which `p` percent of the decayed weight falls.
+    ///
+    /// See `Histogram::percentile` for how the values are estimated.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.quantile(p / 100.0)
+    }
+
+    /// Estimate the values at each of the given quantiles (`0.0..=1.0`) of the decayed weight.
+    ///
+    /// Returns `None` if no values were recorded.
+    pub fn quantiles(&self, quantiles: &[f64]) -> Option<Vec<f64>> {
+        quantiles.iter().map(|&q| self.quantile(q)).collect()
+    }
+
+    fn quantile(&self, quantile: f64) -> Option<f64> {
+        // Decaying all weights by the same factor doesn't move the quantiles.
+        let ranges = self.layout.ranges();
+        let buckets = self.weights.iter().enumerate().map(|(idx, &weight)| {
+            let end = ranges.get(idx + 1).map_or(u64::MAX, |&end| u64::from(end));
+            (u64::from(ranges[idx]), end, weight)
+        });
+        estimate_weighted_quantile(buckets, self.total, quantile)
+    }
+
+    fn half_lives_since_landmark(&self) -> f64 {
+        let elapsed = self.clock.now().saturating_duration_since(self.landmark);
+        elapsed.as_secs_f64() / self.half_life.as_secs_f64()
+    }
+
+    /// Move the landmark `age` half-lives ahead, scaling the weights down to match.
+    fn rescale(&mut self, age: f64) {
+        let factor = (-age).exp2();
+        for weight in self.weights.iter_mut() {
+            *weight *= factor;
+        }
+        self.total *= factor;
+        self.weighted_sum *= factor;
+        self.landmark = self.clock.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use window::tests::ManualClock;
+    use Histogram;
+
+    #[test]
+    fn decay() {
+        let layout = Arc::new(Histogram::linear(1, 1000, 101).layout());
+        let clock = ManualClock::default();
+        let mut hist =
+            DecayingHistogram::with_clock(&layout, Duration::from_secs(10), clock.clone());
+        assert_eq!(None, hist.mean());
+        assert_eq!(None, hist.percentile(50.0));
+
+        hist.accumulate(100, 3);
+        assert!((hist.weight() - 3.0).abs() < 1e-9);
+
+        // After one half-life the old values weigh as much as a single new one.
+        clock.advance(10);
+        assert!((hist.weight() - 1.5).abs() < 1e-9);
+        hist.accumulate(900, 3);
+        assert!((hist.weight() - 4.5).abs() < 1e-9);
+        let mean = hist.mean().unwrap();
+        assert!(
+            (mean - (1.5 * 100.0 + 3.0 * 900.0) / 4.5).abs() < 1e-9,
+            "{}",
+            mean
+        );
+
+        let p25 = hist.percentile(25.0).unwrap();
+        let p50 = hist.percentile(50.0).unwrap();
+        assert!(p25 < 110.0, "{}", p25);
+        assert!(p50 >= 890.0, "{}", p50);
+        assert_eq!(Some(vec![p25, p50]), hist.quantiles(&[0.25, 0.5]));
+    }
+
+    #[test]
+    fn rescale() {
+        let layout = Arc::new(Histogram::exponential(1, 1000, 20).layout());
+        let clock = ManualClock::default();
+        let mut hist =
+            DecayingHistogram::with_clock(&layout, Duration::from_secs(1), clock.clone());
+
+        for _ in 0..10 {
+            hist.add(10);
+            clock.advance(1000);
+        }
+        hist.add(500);
+
+        // Adding long after the landmark moved it instead of overflowing the weights.
+        assert_eq!(clock.now(), hist.landmark);
+        assert!(hist.total.is_finite());
+        assert!((hist.weight() - 1.0).abs() < 1e-9);
+        assert!((hist.mean().unwrap() - 500.0).abs() < 1e-9);
+    }
+}
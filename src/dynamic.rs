@@ -0,0 +1,238 @@
+//! Histograms growing their range to fit the recorded values.
+
+use std::cmp;
+use std::iter;
+
+use super::{bucket_index, Buckets, Histogram, HistogramError};
+
+/// How much each bucket grows over the previous one, before any buckets were merged.
+const INITIAL_GROWTH: f64 = 1.1;
+
+/// An exponential histogram without a fixed range.
+///
+/// Buckets are added as larger values arrive, so values never pile up in the last bucket.
+/// Once there are more than the maximum number of buckets, adjacent buckets are merged,
+/// halving the resolution in exchange for memory.
+///
+/// ```rust
+/// # use histogram::DynamicHistogram;
+/// let mut hist = DynamicHistogram::new(64);
+/// hist.add(3);
+/// hist.add(250_000);
+/// hist.add(4_000_000_000);
+///
+/// assert!(hist.bucket_count() <= 64);
+/// let last = hist.buckets().filter(|b| b.count() > 0).last().unwrap();
+/// assert!(last.start() <= 4_000_000_000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DynamicHistogram {
+    ranges: Vec<u32>,
+    counts: Vec<u64>,
+    /// The end of the last bucket, which can be past `u32::MAX`.
+    end: u64,
+    growth: f64,
+    max_buckets: u32,
+
+    count: u64,
+    sum: u64,
+}
+
+impl DynamicHistogram {
+    /// Create an empty histogram with at most `max_buckets` buckets.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `max_buckets` is invalid, see `try_new`.
+    pub fn new(max_buckets: u32) -> DynamicHistogram {
+        Self::try_new(max_buckets).expect("invalid dynamic histogram")
+    }
+
+    /// Create an empty histogram with at most `max_buckets` buckets.
+    ///
+    /// Fails if there are less than 3 buckets.
+    pub fn try_new(max_buckets: u32) -> Result<DynamicHistogram, HistogramError> {
+        if max_buckets < 3 {
+            return Err(HistogramError::InvalidBucketCount { count: max_buckets });
+        }
+
+        Ok(DynamicHistogram {
+            ranges: vec![0, 1, 2],
+            counts: vec![0; 3],
+            end: 3,
+            growth: INITIAL_GROWTH,
+            max_buckets,
+            count: 0,
+            sum: 0,
+        })
+    }
+
+    /// Add a single value to this histogram.
+    pub fn add(&mut self, value: u32) {
+        self.accumulate(value, 1);
+    }
+
+    /// Add `count` number of values.
+    pub fn accumulate(&mut self, value: u32, count: u32) {
+        if u64::from(value) >= self.end {
+            self.grow(value);
+        }
+
+        let idx = bucket_index(&self.ranges, value);
+        self.counts[idx] += u64::from(count);
+        self.count += u64::from(count);
+        self.sum += u64::from(value) * u64::from(count);
+    }
+
+    /// Get the number of buckets in this histogram.
+    pub fn bucket_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Get an iterator over this histogram's buckets.
+    pub fn buckets(&self) -> Buckets<'_> {
+        Buckets {
+            ranges: &self.ranges,
+            counts: &self.counts,
+            index: 0,
+        }
+    }
+
+    /// Get the sum of all values recorded in this histogram.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Get the number of values recorded in this histogram.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if no values were recorded in this histogram.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get a histogram with the current buckets and the data recorded in them.
+    ///
+    /// The histogram is of type `External`, with a range from 1 to the start of the last bucket.
+    pub fn to_histogram(&self) -> Histogram<Box<[u32]>> {
+        let max = self.ranges[self.ranges.len() - 1];
+        let mut hist = Histogram::try_from_ranges(1, max, self.ranges.clone().into_boxed_slice())
+            .expect("dynamic ranges are always valid");
+        hist.buckets.copy_from_slice(&self.counts);
+        hist.count = self.count;
+        hist.sum = self.sum;
+        hist
+    }
+
+    /// Add buckets until `value` fits, then merge buckets to stay within the maximum.
+    fn grow(&mut self, value: u32) {
+        const LIMIT: u64 = 1 << 32;
+
+        while u64::from(value) >= self.end {
+            let start = self.end;
+            let end = cmp::max(start + 1, (start as f64 * self.growth).round() as u64);
+            self.ranges.push(start as u32);
+            self.counts.push(0);
+            self.end = cmp::min(end, LIMIT);
+        }
+
+        while self.counts.len() > self.max_buckets as usize {
+            self.merge_buckets();
+        }
+    }
+
+    /// Merge each pair of adjacent buckets, except for the bucket of 0.
+    fn merge_buckets(&mut self) {
+        self.ranges = iter::once(0)
+            .chain(self.ranges[1..].iter().step_by(2).cloned())
+            .collect();
+        self.counts = iter::once(self.counts[0])
+            .chain(self.counts[1..].chunks(2).map(|pair| pair.iter().sum()))
+            .collect();
+        self.growth *= self.growth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grow() {
+        let mut hist = DynamicHistogram::new(1000);
+        for i in 0..3 {
+            hist.add(i);
+        }
+        assert_eq!(3, hist.bucket_count());
+
+        hist.add(1000);
+        hist.add(u32::MAX);
+        assert_eq!(5, hist.count());
+        assert_eq!(u64::from(u32::MAX) + 1003, hist.sum());
+
+        let recorded = hist
+            .buckets()
+            .filter(|b| b.count() > 0)
+            .map(|b| b.start())
+            .collect::<Vec<_>>();
+        assert_eq!(5, recorded.len());
+        let start_1000 = recorded[3];
+        assert!(start_1000 <= 1000 && f64::from(start_1000) * 1.1 > 1000.0);
+        assert!(f64::from(recorded[4]) * 1.1 > f64::from(u32::MAX));
+    }
+
+    #[test]
+    fn merge_buckets() {
+        let values = (0..10_000).map(|i| i * i).collect::<Vec<u32>>();
+        let mut hist = DynamicHistogram::new(20);
+        for &value in &values {
+            hist.add(value);
+        }
+
+        assert!(hist.bucket_count() <= 20);
+        assert!(hist.bucket_count() > 10);
+        assert_eq!(10_000, hist.count());
+        assert_eq!(
+            values.iter().map(|&v| u64::from(v)).sum::<u64>(),
+            hist.sum()
+        );
+
+        for bucket in hist.buckets() {
+            let count = values
+                .iter()
+                .filter(|&&v| v >= bucket.start() && v < bucket.end())
+                .count();
+            assert_eq!(count as u64, bucket.count(), "{:?}", bucket);
+        }
+    }
+
+    #[test]
+    fn to_histogram() {
+        let mut dynamic = DynamicHistogram::new(50);
+        for &value in &[0, 5, 5, 70, 1200, 90_000] {
+            dynamic.add(value);
+        }
+
+        let mut hist = dynamic.to_histogram();
+        assert_eq!(dynamic.bucket_count(), hist.bucket_count());
+        assert_eq!(dynamic.count(), hist.count());
+        assert_eq!(dynamic.sum(), hist.sum());
+        let counts = |buckets: Buckets<'_>| buckets.map(|b| b.count()).collect::<Vec<_>>();
+        assert_eq!(counts(dynamic.buckets()), counts(hist.buckets()));
+
+        // The fixed layout records further values into the same buckets.
+        hist.add(70);
+        dynamic.add(70);
+        assert_eq!(counts(dynamic.buckets()), counts(hist.buckets()));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        match DynamicHistogram::try_new(2) {
+            Err(HistogramError::InvalidBucketCount { count: 2 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Errors returned by histogram operations.
+
+use serde_json;
+use std::error;
+use std::fmt;
+
+/// An error that occurred while working with a histogram.
+#[derive(Debug)]
+pub enum HistogramError {
+    /// The number of buckets is not valid for the histogram type.
+    InvalidBucketCount { count: u32 },
+    /// The number of significant digits is not supported.
+    InvalidPrecision { digits: u32 },
+    /// The relative accuracy of a sketch is not between 0 and 1.
+    InvalidAccuracy { accuracy: f64 },
+    /// The minimum of the range is not below its maximum.
+    InvalidRange { min: u32, max: u32 },
+    /// The bucket ranges are not strictly increasing.
+    NonMonotonicRanges,
+    /// Externally provided ranges don't end with the `INT_MAX` sentinel.
+    MissingSentinel,
+    /// A `HistogramBuilder` is missing parameters or has conflicting ones.
+    InvalidBuilder { reason: &'static str },
+    /// The data has a different number of buckets than the histogram.
+    BucketCountMismatch { expected: usize, actual: usize },
+    /// The histograms have different ranges or types.
+    LayoutMismatch,
+    /// A count is lower than in an earlier snapshot, as if the histogram was cleared in between.
+    CounterReset,
+    /// A keyed histogram already recorded the maximum number of keys.
+    KeyLimitReached { limit: usize },
+    /// A label name is not valid, reserved, or used twice.
+    InvalidLabel { name: String },
+    /// The data could not be parsed.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for HistogramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HistogramError::InvalidBucketCount { count } => {
+                write!(f, "invalid number of buckets: {}", count)
+            }
+            HistogramError::InvalidPrecision { digits } => {
+                write!(f, "invalid number of significant digits: {}", digits)
+            }
+            HistogramError::InvalidAccuracy { accuracy } => {
+                write!(f, "invalid relative accuracy: {}", accuracy)
+            }
+            HistogramError::InvalidRange { min, max } => {
+                write!(f, "invalid range: {} is not below {}", min, max)
+            }
+            HistogramError::NonMonotonicRanges => {
+                write!(f, "bucket ranges are not strictly increasing")
+            }
+            HistogramError::MissingSentinel => {
+                write!(f, "bucket ranges don't end with the INT_MAX sentinel")
+            }
+            HistogramError::InvalidBuilder { reason } => {
+                write!(f, "invalid histogram definition: {}", reason)
+            }
+            HistogramError::BucketCountMismatch { expected, actual } => {
+                write!(f, "expected data for {} buckets, got {}", expected, actual)
+            }
+            HistogramError::LayoutMismatch => {
+                write!(f, "histograms have different bucket layouts")
+            }
+            HistogramError::CounterReset => {
+                write!(f, "counts are lower than in the earlier snapshot")
+            }
+            HistogramError::KeyLimitReached { limit } => {
+                write!(f, "reached the limit of {} keys", limit)
+            }
+            HistogramError::InvalidLabel { ref name } => {
+                write!(f, "invalid, reserved or repeated label name: {:?}", name)
+            }
+            HistogramError::Json(ref e) => write!(f, "invalid histogram data: {}", e),
+        }
+    }
+}
+
+impl error::Error for HistogramError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            HistogramError::Json(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for HistogramError {
+    fn from(e: serde_json::Error) -> HistogramError {
+        HistogramError::Json(e)
+    }
+}
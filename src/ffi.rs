@@ -1,17 +1,25 @@
 //! Create, modify and serialize histograms over FFI.
+//!
+//! All functions taking a pointer expect it to be a valid, non-null pointer
+//! previously returned by the matching function of this module.
+
+#![allow(clippy::missing_safety_doc)]
 
 use serde_json;
 use std::ffi::CString;
 use std::os::raw::{c_char, c_int, c_uint};
-use std::slice;
+use std::{ptr, slice};
 
-use super::Histogram;
-use super::Snapshot;
+use super::validate_ranges;
+use super::{Histogram, HistogramError, Snapshot, Type};
 
 /// A histogram created from static data for ranges.
 pub type StaticHistogram = Histogram<&'static [u32]>;
 
 /// Create a new histogram from an external array of ranges.
+///
+/// `ranges` holds the start of each of the `bucket_count` buckets, followed by `INT_MAX`.
+/// Returns a null pointer if the ranges are invalid.
 #[no_mangle]
 pub unsafe extern "C" fn histogram_factory_get(
     min: c_uint,
@@ -20,18 +28,38 @@ pub unsafe extern "C" fn histogram_factory_get(
     ranges: *const c_int,
 ) -> *mut StaticHistogram {
     let ranges: &'static [u32] = slice::from_raw_parts(ranges as *const c_uint, bucket_count + 1);
-    assert_eq!(::std::i32::MAX, ranges[bucket_count] as i32);
-    let h = Histogram {
-        min: min,
-        max: max,
+    match static_histogram(min, max, ranges) {
+        Ok(h) => Box::into_raw(Box::new(h)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Create a histogram from ranges including the trailing sentinel.
+fn static_histogram(
+    min: u32,
+    max: u32,
+    ranges: &'static [u32],
+) -> Result<StaticHistogram, HistogramError> {
+    let bucket_count = ranges.len() - 1;
+    if bucket_count == 0 {
+        return Err(HistogramError::InvalidBucketCount { count: 0 });
+    }
+    if ranges[bucket_count] != i32::MAX as u32 {
+        return Err(HistogramError::MissingSentinel);
+    }
+    validate_ranges(min, max, ranges)?;
+
+    Ok(Histogram {
+        min,
+        max,
         ranges,
-        buckets: vec![0; bucket_count as usize].into_boxed_slice(),
+        buckets: vec![0; bucket_count].into_boxed_slice(),
         count: 0,
         sum: 0,
-        typ: super::Type::External,
-    };
-
-    Box::into_raw(Box::new(h))
+        typ: Type::External,
+        bits: 0,
+        meta: None,
+    })
 }
 
 /// Free a histogram's memory.
@@ -47,6 +75,39 @@ pub unsafe extern "C" fn histogram_add(histogram: *mut StaticHistogram, sample:
     histogram.add(sample);
 }
 
+/// Add `len` values from `samples` to the given histogram.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_add_many(
+    histogram: *mut StaticHistogram,
+    samples: *const c_uint,
+    len: usize,
+) {
+    let histogram = &mut *histogram;
+    if len == 0 {
+        return;
+    }
+    histogram.add_many(slice::from_raw_parts(samples, len));
+}
+
+/// Add `counts[i]` number of `values[i]` to the given histogram, for each of the `len` values.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_accumulate_many(
+    histogram: *mut StaticHistogram,
+    values: *const c_uint,
+    counts: *const c_uint,
+    len: usize,
+) {
+    let histogram = &mut *histogram;
+    if len == 0 {
+        return;
+    }
+    let values = slice::from_raw_parts(values, len);
+    let counts = slice::from_raw_parts(counts, len);
+    for (&value, &count) in values.iter().zip(counts) {
+        histogram.accumulate(value, count);
+    }
+}
+
 /// Clear the stored data in the histogram
 #[no_mangle]
 pub unsafe extern "C" fn histogram_clear(histogram: *mut StaticHistogram) {
@@ -61,6 +122,20 @@ pub unsafe extern "C" fn histogram_is_empty(histogram: *const StaticHistogram) -
     histogram.is_empty()
 }
 
+/// Get the total sum of values recorded in this histogram.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_sum(histogram: *const StaticHistogram) -> u64 {
+    let histogram = &*histogram;
+    histogram.sum()
+}
+
+/// Get the total count of values recorded in this histogram.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_count(histogram: *const StaticHistogram) -> u64 {
+    let histogram = &*histogram;
+    histogram.count()
+}
+
 /// Get the number of buckets in this histogram.
 #[no_mangle]
 pub unsafe extern "C" fn histogram_bucket_count(histogram: *const StaticHistogram) -> usize {
@@ -79,7 +154,9 @@ pub unsafe extern "C" fn histogram_ranges(histogram: *const StaticHistogram, idx
 /// The returned data is null-terminated. It should be passed back to `histogram_free_cstr` to
 /// deallocate after usage.
 #[no_mangle]
-pub unsafe extern "C" fn histogram_serialize_persist(histogram: *mut StaticHistogram) -> *mut c_char {
+pub unsafe extern "C" fn histogram_serialize_persist(
+    histogram: *mut StaticHistogram,
+) -> *mut c_char {
     let histogram = &*histogram;
     let serialized = serde_json::to_string(&histogram.persisted()).unwrap();
     CString::new(serialized.to_string()).unwrap().into_raw()
@@ -107,14 +184,44 @@ pub unsafe extern "C" fn histogram_snapshot(histogram: *const StaticHistogram) -
     Box::into_raw(Box::new(histogram.snapshot()))
 }
 
+/// Get a copy of the data recorded in the histogram and clear it.
+///
+/// The returned snapshot should be passed back to `histogram_snapshot_free` to deallocate after
+/// usage.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_snapshot_and_clear(
+    histogram: *mut StaticHistogram,
+) -> *mut Snapshot {
+    let histogram = &mut *histogram;
+    Box::into_raw(Box::new(histogram.snapshot_and_clear()))
+}
+
+/// Get the data recorded in the histogram since `snapshot` was taken, leaving the histogram as is.
+///
+/// Returns null if the snapshot was not taken from this histogram
+/// or the histogram was cleared since.
+/// The returned snapshot should be passed back to `histogram_snapshot_free` to deallocate after
+/// usage.
+#[no_mangle]
+pub unsafe extern "C" fn histogram_delta_since(
+    histogram: *const StaticHistogram,
+    snapshot: *const Snapshot,
+) -> *mut Snapshot {
+    let histogram = &*histogram;
+    match histogram.delta_since(&*snapshot) {
+        Ok(delta) => Box::into_raw(Box::new(delta)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
-pub unsafe extern "C" fn histogram_snapshot_counts(snapshot: *const Snapshot, idx: c_int) -> u32 {
+pub unsafe extern "C" fn histogram_snapshot_counts(snapshot: *const Snapshot, idx: c_int) -> u64 {
     let snapshot = &*snapshot;
     snapshot.counts[idx as usize]
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn histogram_snapshot_sum(snapshot: *const Snapshot) -> u32 {
+pub unsafe extern "C" fn histogram_snapshot_sum(snapshot: *const Snapshot) -> u64 {
     let snapshot = &*snapshot;
     snapshot.sum
 }
@@ -0,0 +1,176 @@
+//! Keyed histograms, recording a histogram per string key.
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+
+use serde::ser::{Serialize, Serializer};
+
+use super::{Histogram, HistogramError, SharedLayout, Snapshot};
+
+/// A collection of histograms sharing one bucket layout, indexed by a string key.
+///
+/// Histograms for new keys are created on first use.
+/// They share the ranges of the prototype histogram, so use cheaply clonable ranges
+/// such as a `SharedLayout` (see `Histogram::from_layout`), `Arc<[u32]>`
+/// (see `Histogram::into_shared`) or `&'static [u32]`.
+#[derive(Debug)]
+pub struct KeyedHistogram<T: AsRef<[u32]> + Clone = SharedLayout> {
+    prototype: Histogram<T>,
+    histograms: BTreeMap<String, Histogram<T>>,
+    key_limit: Option<usize>,
+}
+
+impl<T: AsRef<[u32]> + Clone> KeyedHistogram<T> {
+    /// Create a keyed histogram, with the same layout as `prototype` for every key.
+    ///
+    /// Values recorded in `prototype` are ignored.
+    pub fn new(prototype: Histogram<T>) -> KeyedHistogram<T> {
+        KeyedHistogram {
+            prototype: prototype.empty_like(),
+            histograms: BTreeMap::new(),
+            key_limit: None,
+        }
+    }
+
+    /// Create a keyed histogram, recording at most `limit` different keys.
+    pub fn with_key_limit(prototype: Histogram<T>, limit: usize) -> KeyedHistogram<T> {
+        let mut keyed = Self::new(prototype);
+        keyed.key_limit = Some(limit);
+        keyed
+    }
+
+    /// Add a single value to the histogram of `key`.
+    ///
+    /// Fails without recording the value if `key` is new and the key limit is reached.
+    pub fn add(&mut self, key: &str, value: u32) -> Result<(), HistogramError> {
+        self.histogram_mut(key)?.add(value);
+        Ok(())
+    }
+
+    fn histogram_mut(&mut self, key: &str) -> Result<&mut Histogram<T>, HistogramError> {
+        if !self.histograms.contains_key(key) {
+            if let Some(limit) = self.key_limit {
+                if self.histograms.len() >= limit {
+                    return Err(HistogramError::KeyLimitReached { limit });
+                }
+            }
+
+            let histogram = self.prototype.empty_like();
+            self.histograms.insert(key.to_string(), histogram);
+        }
+
+        Ok(self
+            .histograms
+            .get_mut(key)
+            .expect("histogram was just inserted"))
+    }
+
+    /// Get the histogram of `key`, if any values were recorded for it.
+    pub fn get(&self, key: &str) -> Option<&Histogram<T>> {
+        self.histograms.get(key)
+    }
+
+    /// Get an iterator over all recorded keys, in sorted order.
+    pub fn keys(&self) -> Keys<'_, T> {
+        Keys {
+            inner: self.histograms.keys(),
+        }
+    }
+
+    /// Get a copy of the data currently recorded for `key`.
+    pub fn snapshot(&self, key: &str) -> Option<Snapshot> {
+        self.get(key).map(Histogram::snapshot)
+    }
+
+    /// Get the number of recorded keys.
+    pub fn len(&self) -> usize {
+        self.histograms.len()
+    }
+
+    /// Check if any key was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.histograms.is_empty()
+    }
+
+    /// Remove all keys and their data.
+    pub fn clear(&mut self) {
+        self.histograms.clear();
+    }
+}
+
+/// An iterator over the keys of a keyed histogram.
+#[derive(Debug, Clone)]
+pub struct Keys<'a, T: 'a + AsRef<[u32]>> {
+    inner: btree_map::Keys<'a, String, Histogram<T>>,
+}
+
+impl<'a, T: AsRef<[u32]>> Iterator for Keys<'a, T> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|key| key.as_str())
+    }
+}
+
+impl<T: AsRef<[u32]> + Clone> Serialize for KeyedHistogram<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.histograms.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::sync::Arc;
+
+    #[test]
+    fn keyed() {
+        let prototype = Histogram::exponential(1, 500, 10).into_shared();
+        let mut keyed = KeyedHistogram::new(prototype);
+        assert!(keyed.is_empty());
+
+        keyed.add("b", 14).unwrap();
+        keyed.add("a", 1).unwrap();
+        keyed.add("b", 450).unwrap();
+
+        assert_eq!(vec!["a", "b"], keyed.keys().collect::<Vec<_>>());
+        assert_eq!(2, keyed.get("b").unwrap().count());
+        assert_eq!(14 + 450, keyed.snapshot("b").unwrap().sum);
+        assert!(keyed.snapshot("c").is_none());
+        assert!(Arc::ptr_eq(
+            &keyed.get("a").unwrap().ranges,
+            &keyed.get("b").unwrap().ranges
+        ));
+
+        let serialized = serde_json::to_string(&keyed).unwrap();
+        let expected = format!(
+            r#"{{"a":{},"b":{}}}"#,
+            serde_json::to_string(keyed.get("a").unwrap()).unwrap(),
+            serde_json::to_string(keyed.get("b").unwrap()).unwrap()
+        );
+        assert_eq!(expected, serialized);
+
+        keyed.clear();
+        assert!(keyed.is_empty());
+    }
+
+    #[test]
+    fn key_limit() {
+        let mut keyed = KeyedHistogram::with_key_limit(Histogram::boolean().into_shared(), 2);
+        keyed.add("a", 1).unwrap();
+        keyed.add("b", 1).unwrap();
+
+        match keyed.add("c", 1) {
+            Err(HistogramError::KeyLimitReached { limit: 2 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        keyed.add("a", 0).unwrap();
+
+        assert_eq!(2, keyed.len());
+        assert_eq!(2, keyed.get("a").unwrap().count());
+    }
+}
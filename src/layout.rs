@@ -0,0 +1,173 @@
+//! Bucket layouts that can be shared between histograms.
+
+use std::sync::Arc;
+
+use super::{Histogram, Type};
+
+/// The layout of a histogram: its type, range and the start of each bucket.
+///
+/// A layout can be built once and shared by many histograms through `Histogram::from_layout`,
+/// without each of them allocating a copy of the ranges.
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// # use histogram::{BucketLayout, Histogram};
+/// let layout = Arc::new(Histogram::exponential(1, 500, 10).layout());
+/// let mut a = Histogram::from_layout(&layout);
+/// let b = Histogram::from_layout(&layout);
+///
+/// a.add(14);
+/// assert_eq!(layout.fingerprint(), b.layout().fingerprint());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BucketLayout {
+    min: u32,
+    max: u32,
+    typ: Type,
+    bits: u32,
+    ranges: Box<[u32]>,
+}
+
+impl BucketLayout {
+    /// The minimum of the histogram range.
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+
+    /// The maximum of the histogram range.
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// The type of histograms using this layout.
+    pub fn kind(&self) -> Type {
+        self.typ
+    }
+
+    /// The bits of precision of a log-linear layout.
+    pub(crate) fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The start of each bucket.
+    pub fn ranges(&self) -> &[u32] {
+        &self.ranges
+    }
+
+    /// Get the number of buckets.
+    pub fn bucket_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// A hash of this layout, stable across runs and platforms.
+    ///
+    /// Layouts with the same fingerprint can be assumed to be equal.
+    pub fn fingerprint(&self) -> u64 {
+        // 64-bit FNV-1a
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        [self.min, self.max, self.typ as u32]
+            .iter()
+            .chain(self.ranges.iter())
+            .flat_map(|word| word.to_le_bytes())
+            .fold(OFFSET_BASIS, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+            })
+    }
+}
+
+impl<'a, T: AsRef<[u32]>> From<&'a Histogram<T>> for BucketLayout {
+    fn from(histogram: &'a Histogram<T>) -> BucketLayout {
+        BucketLayout {
+            min: histogram.min,
+            max: histogram.max,
+            typ: histogram.typ,
+            bits: histogram.bits,
+            ranges: histogram.ranges().into(),
+        }
+    }
+}
+
+/// Ranges of a histogram, taken from a shared `BucketLayout`.
+#[derive(Debug, Clone)]
+pub struct SharedLayout(Arc<BucketLayout>);
+
+impl SharedLayout {
+    /// The layout these ranges belong to.
+    pub fn layout(&self) -> &Arc<BucketLayout> {
+        &self.0
+    }
+}
+
+impl AsRef<[u32]> for SharedLayout {
+    fn as_ref(&self) -> &[u32] {
+        &self.0.ranges
+    }
+}
+
+impl Histogram<SharedLayout> {
+    /// Create an empty histogram using the shared `layout`.
+    pub fn from_layout(layout: &Arc<BucketLayout>) -> Histogram<SharedLayout> {
+        Histogram {
+            min: layout.min,
+            max: layout.max,
+            ranges: SharedLayout(Arc::clone(layout)),
+            buckets: vec![0; layout.bucket_count()].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            typ: layout.typ,
+            bits: layout.bits,
+            meta: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_layout() {
+        let layout = Arc::new(Histogram::exponential(1, 500, 10).layout());
+        assert_eq!(10, layout.bucket_count());
+        assert_eq!(Type::Exponential, layout.kind());
+
+        let mut a = Histogram::from_layout(&layout);
+        let mut b = Histogram::from_layout(&layout);
+        a.add(14);
+        b.add(450);
+        assert!(Arc::ptr_eq(a.ranges.layout(), b.ranges.layout()));
+
+        let mut plain = Histogram::exponential(1, 500, 10);
+        plain.merge(&a).unwrap();
+        plain.merge(&b).unwrap();
+        assert_eq!(2, plain.count());
+        assert_eq!(*layout, plain.layout());
+
+        let log_linear = Histogram::log_linear(2);
+        let layout = Arc::new(log_linear.layout());
+        assert_eq!(log_linear.bits, layout.bits());
+        let mut shared = Histogram::from_layout(&layout);
+        let mut plain = log_linear.empty_like();
+        for &value in &[3, 700, 123_456, u32::MAX] {
+            shared.add(value);
+            plain.add(value);
+        }
+        assert_eq!(plain.snapshot(), shared.snapshot());
+    }
+
+    #[test]
+    fn fingerprint() {
+        let a = Histogram::exponential(1, 500, 10).layout();
+        let b = Histogram::exponential(1, 500, 10).layout();
+        let c = Histogram::linear(1, 500, 10).layout();
+        let d = Histogram::exponential(1, 500, 11).layout();
+
+        assert_eq!(a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a, c);
+        assert_ne!(a.fingerprint(), c.fingerprint());
+        assert_ne!(a.fingerprint(), d.fingerprint());
+    }
+}
@@ -2,10 +2,13 @@
 //!
 //! A simple library for histograms, with features required by Firefox.
 //!
-//! It supports linear, exponential, bool and enumerated histograms.
+//! It supports linear, exponential, log-linear, bool, count, enumerated, categorical and sparse histograms.
 //! It can be serialized to a packed and a full representation.
 //! It can be constructed from FFI-provided bucket boundaries,
 //! avoiding additional allocation for metadata.
+//! Log-linear histograms of `u64` values are available as `LogLinearHistogram`.
+//! Quantiles of unbounded values can be estimated within a relative error using a `DDSketch`.
+//! Histograms can be exported in the Prometheus text format using a `PrometheusEncoder`.
 //!
 //! ## Example
 //!
@@ -33,19 +36,73 @@ extern crate serde_derive;
 use std::cmp;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::Arc;
 
+use serde::de::{self, Deserialize, Deserializer};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
+pub mod atomic;
+mod builder;
+pub mod categorical;
+pub mod decay;
+pub mod dynamic;
+mod error;
 pub mod ffi;
+pub mod keyed;
+mod layout;
+pub mod log_linear;
+pub mod prometheus;
+pub mod sketch;
+pub mod sparse;
+pub mod window;
+
+pub use atomic::AtomicHistogram;
+pub use builder::HistogramBuilder;
+pub use categorical::CategoricalHistogram;
+pub use decay::DecayingHistogram;
+pub use dynamic::DynamicHistogram;
+pub use error::HistogramError;
+pub use keyed::KeyedHistogram;
+pub use layout::{BucketLayout, SharedLayout};
+pub use log_linear::LogLinearHistogram;
+pub use prometheus::PrometheusEncoder;
+pub use sketch::DDSketch;
+pub use sparse::SparseHistogram;
+pub use window::WindowedHistogram;
 
 /// The type of a histogram.
-#[derive(Copy, Clone, Debug, Serialize)]
+///
+/// The numbering matches Firefox's Telemetry histogram types.
+/// `External`, `LogLinear` and `Sparse` histograms are not known to Firefox
+/// and use numbers outside of its range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 pub enum Type {
     Exponential = 0,
     Linear = 1,
     Boolean = 2,
     Flag = 3,
-    External = 4,
+    Count = 4,
+    Categorical = 5,
+    External = 6,
+    LogLinear = 7,
+    Sparse = 8,
+}
+
+impl Type {
+    fn from_u32(typ: u32) -> Option<Type> {
+        match typ {
+            0 => Some(Type::Exponential),
+            1 => Some(Type::Linear),
+            2 => Some(Type::Boolean),
+            3 => Some(Type::Flag),
+            4 => Some(Type::Count),
+            5 => Some(Type::Categorical),
+            6 => Some(Type::External),
+            7 => Some(Type::LogLinear),
+            8 => Some(Type::Sparse),
+            _ => None,
+        }
+    }
 }
 
 /// A histogram.
@@ -57,26 +114,42 @@ pub struct Histogram<T: AsRef<[u32]>> {
     min: u32,
     max: u32,
     ranges: T,
-    buckets: Box<[u32]>,
+    buckets: Box<[u64]>,
 
-    count: u32,
-    sum: u32,
+    count: u64,
+    sum: u64,
     typ: Type,
+    /// Bits of precision of log-linear histograms, 0 for other types.
+    bits: u32,
+    meta: Option<Box<Metadata>>,
 }
 
-#[derive(Debug)]
+/// Descriptive data of a histogram, kept out of line as most histograms don't have any.
+#[derive(Debug, Clone, Default)]
+struct Metadata {
+    name: Option<String>,
+    unit: Option<String>,
+}
+
+/// A copy of the data recorded in a histogram at some point in time.
+///
+/// It keeps the start of each bucket, so it can be inspected and serialized on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Snapshot {
-    counts: Box<[u32]>,
-    sum: u32,
+    ranges: Box<[u32]>,
+    counts: Box<[u64]>,
+    sum: u64,
 }
 
 fn linear_range(min: u32, max: u32, count: u32) -> Vec<u32> {
     let mut ranges = Vec::with_capacity(count as usize);
     ranges.push(0);
 
+    // Wide ranges overflow `u32` before the division brings them back into range.
+    let (min, max, count) = (u64::from(min), u64::from(max), u64::from(count));
     for i in 1..count {
         let range = (min * (count - 1 - i) + max * (i - 1)) / (count - 2);
-        ranges.push(range);
+        ranges.push(range as u32);
     }
 
     ranges
@@ -109,12 +182,184 @@ fn exponential_range(min: u32, max: u32, count: u32) -> Vec<u32> {
     ranges
 }
 
-fn pack_histogram<T: AsRef<[u32]>>(buckets: Buckets<T>) -> Vec<(u32, u32)> {
+/// Largest number of significant digits log-linear histograms can keep apart.
+const MAX_SIGNIFICANT_DIGITS: u32 = 3;
+
+/// Number of bits of precision needed to keep apart values differing in `significant_digits` digits.
+fn log_linear_bits(significant_digits: u32) -> u32 {
+    let sub_buckets = 2 * 10u32.pow(significant_digits);
+    32 - (sub_buckets - 1).leading_zeros()
+}
+
+/// Number of buckets of a log-linear layout covering all values of `value_bits` bits.
+fn log_linear_bucket_count(bits: u32, value_bits: u32) -> usize {
+    (1 << bits) + (value_bits - bits) as usize * (1 << (bits - 1))
+}
+
+/// Find the number of significant digits of a log-linear layout with `bucket_count` buckets.
+fn log_linear_digits(bucket_count: usize, value_bits: u32) -> Option<u32> {
+    (1..=MAX_SIGNIFICANT_DIGITS).find(|&digits| {
+        log_linear_bucket_count(log_linear_bits(digits), value_bits) == bucket_count
+    })
+}
+
+/// Values below `2^bits` get a bucket each,
+/// every larger power of two is split into `2^(bits - 1)` buckets of equal width.
+fn log_linear_range(bits: u32) -> Vec<u32> {
+    (0..log_linear_bucket_count(bits, 32))
+        .map(|idx| log_linear_start(bits, idx) as u32)
+        .collect()
+}
+
+/// Find the index of the bucket `value` falls into in a log-linear layout with `bits` of precision.
+///
+/// The layouts for `u32` and `u64` values share their buckets up to `u32::MAX`.
+fn log_linear_index(bits: u32, value: u64) -> usize {
+    if value < 1 << bits {
+        return value as usize;
+    }
+
+    // Each power of two above `2^bits` adds `2^(bits - 1)` buckets
+    // and the top `bits` bits of the value select one of them.
+    let magnitude = 63 - value.leading_zeros();
+    let shift = magnitude - bits + 1;
+    ((u64::from(shift) << (bits - 1)) + (value >> shift)) as usize
+}
+
+/// Find the start of the bucket at `idx` in a log-linear layout with `bits` of precision.
+fn log_linear_start(bits: u32, idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < 1 << bits {
+        return idx;
+    }
+
+    let shift = (idx >> (bits - 1)) - 1;
+    (idx - (shift << (bits - 1))) << shift
+}
+
+/// Check that `ranges` are valid bucket starts for a histogram from `min` to `max`.
+fn validate_ranges(min: u32, max: u32, ranges: &[u32]) -> Result<(), HistogramError> {
+    if min >= max {
+        return Err(HistogramError::InvalidRange { min, max });
+    }
+    if ranges.is_empty() {
+        return Err(HistogramError::InvalidBucketCount { count: 0 });
+    }
+    if ranges.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(HistogramError::NonMonotonicRanges);
+    }
+    Ok(())
+}
+
+/// Lower values above `i32::MAX - 1` by one before adding them, like Firefox does.
+///
+/// Log-linear histograms cover all `u32` values and record them as they are.
+fn adjust_value(typ: Type, value: u32) -> u32 {
+    if typ != Type::LogLinear && value > (i32::MAX - 1) as u32 {
+        value - 1
+    } else {
+        value
+    }
+}
+
+/// Find the index of the bucket `value` falls into.
+///
+/// Linear and log-linear layouts compute the index directly,
+/// all other layouts search the `ranges`.
+/// `bits` is the precision of log-linear layouts and ignored for other types.
+fn find_bucket(typ: Type, min: u32, max: u32, bits: u32, ranges: &[u32], value: u32) -> usize {
+    let len = ranges.len();
+    if len < 3 || value < min {
+        return bucket_index(ranges, value);
+    }
+
+    let guess = match typ {
+        Type::Linear | Type::Boolean | Type::Flag | Type::Count | Type::Categorical => {
+            let offset = u64::from(value - min) * (len - 2) as u64 / u64::from(max - min);
+            1 + offset as usize
+        }
+        Type::LogLinear => return log_linear_index(bits, u64::from(value)),
+        // Estimating exponential indices from logarithms is slower than searching,
+        // see the exponential groups in benches/bucket_lookup.rs.
+        Type::Exponential | Type::External | Type::Sparse => return bucket_index(ranges, value),
+    };
+
+    refine_bucket(ranges, value, guess)
+}
+
+/// Correct an estimated bucket index by a few steps, or search for it if the guess is too far off.
+fn refine_bucket(ranges: &[u32], value: u32, guess: usize) -> usize {
+    const MAX_STEPS: usize = 2;
+
+    let mut idx = cmp::min(guess, ranges.len() - 1);
+    for _ in 0..MAX_STEPS {
+        if ranges[idx] > value {
+            idx -= 1;
+        } else if idx + 1 < ranges.len() && ranges[idx + 1] <= value {
+            idx += 1;
+        } else {
+            return idx;
+        }
+    }
+
+    bucket_index(ranges, value)
+}
+
+/// Find the index of the bucket `value` falls into by searching the `ranges`.
+fn bucket_index(ranges: &[u32], value: u32) -> usize {
+    let mut under = 0;
+    let mut over = ranges.len();
+    let mut mid;
+
+    loop {
+        mid = under + (over - under) / 2;
+        if mid == under {
+            break;
+        }
+        if ranges[mid] <= value {
+            under = mid;
+        } else {
+            over = mid;
+        }
+    }
+
+    mid
+}
+
+/// Number of samples assigned to buckets together by `Histogram::add_many`.
+const BATCH_SIZE: usize = 16;
+
+/// Largest bucket count for which searching a batch of samples at once beats searching each sample.
+const MAX_BATCHED_BUCKETS: usize = 32;
+
+/// Find the bucket of each of the `values` by searching the `ranges`, storing it in `indices`.
+///
+/// All searches take the same number of steps and select the next step without branching,
+/// so each step can be done for the whole batch at once.
+fn batch_bucket_indices(
+    ranges: &[u32],
+    values: &[u32; BATCH_SIZE],
+    indices: &mut [usize; BATCH_SIZE],
+) {
+    *indices = [0; BATCH_SIZE];
+
+    let mut len = ranges.len();
+    while len > 1 {
+        let half = len / 2;
+        let upper = &ranges[half..];
+        for (idx, &value) in indices.iter_mut().zip(values.iter()) {
+            *idx += (upper[*idx] <= value) as usize * half;
+        }
+        len -= half;
+    }
+}
+
+fn pack_histogram(buckets: Buckets<'_>) -> Vec<(u32, u64)> {
     let mut res = vec![];
 
     let mut first = true;
     let mut last = 0;
-    let len = buckets.histogram.bucket_count();
+    let len = buckets.counts.len();
     let mut last_start = 42;
     let mut previous_start = 0;
 
@@ -146,9 +391,23 @@ impl<T: AsRef<[u32]>> Histogram<T> {
     /// ## Requirements
     ///
     /// * `ranges.len()` is the number of buckets
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the ranges are invalid, see `try_from_ranges`.
     pub fn factory_get(min: u32, max: u32, ranges: T) -> Histogram<T> {
+        Self::try_from_ranges(min, max, ranges).expect("invalid histogram ranges")
+    }
+
+    /// Create a histogram with a range of min..max from the given ranges.
+    ///
+    /// `ranges` contains the start of each bucket.
+    /// Fails if `min` is not below `max`, `ranges` is empty or not strictly increasing.
+    pub fn try_from_ranges(min: u32, max: u32, ranges: T) -> Result<Histogram<T>, HistogramError> {
+        validate_ranges(min, max, ranges.as_ref())?;
+
         let len = ranges.as_ref().len();
-        Histogram {
+        Ok(Histogram {
             min,
             max,
             ranges,
@@ -156,7 +415,9 @@ impl<T: AsRef<[u32]>> Histogram<T> {
             count: 0,
             sum: 0,
             typ: Type::External,
-        }
+            bits: 0,
+            meta: None,
+        })
     }
 
     /// Get the number of buckets in this histogram.
@@ -165,67 +426,189 @@ impl<T: AsRef<[u32]>> Histogram<T> {
     }
 
     /// Add a single value to this histogram.
-    pub fn add(&mut self, mut value: u32) {
-        if value > (i32::max_value()-1) as u32 {
-            value -= 1;
-        }
+    pub fn add(&mut self, value: u32) {
+        let value = adjust_value(self.typ, value);
         self.accumulate(value, 1);
     }
 
     /// Add `count` number of values.
+    ///
+    /// Count histograms ignore the value and increase their counter by `count`.
     pub fn accumulate(&mut self, value: u32, count: u32) {
-        self.sum += value * count;
-        self.count += count;
-        *self.bucket(value) += 1;
+        if self.typ == Type::Count {
+            self.sum += u64::from(count);
+            self.count += u64::from(count);
+            self.buckets[0] += u64::from(count);
+            return;
+        }
+
+        self.sum += u64::from(value) * u64::from(count);
+        self.count += u64::from(count);
+        *self.bucket(value) += u64::from(count);
+    }
+
+    /// Add all values in `samples`.
+    ///
+    /// Records the same data as calling `add` for each sample.
+    /// Small exponential and custom layouts assign buckets to whole batches of samples at once.
+    pub fn add_many(&mut self, samples: &[u32]) {
+        let len = samples.len() as u64;
+        if self.typ == Type::Count {
+            self.sum += len;
+            self.count += len;
+            self.buckets[0] += len;
+            return;
+        }
+
+        let mut sum = 0;
+        let ranges = &self.ranges.as_ref()[..self.buckets.len()];
+        let batched = match self.typ {
+            Type::Exponential | Type::External => ranges.len() <= MAX_BATCHED_BUCKETS,
+            _ => false,
+        };
+        if batched {
+            let mut values = [0; BATCH_SIZE];
+            let mut indices = [0; BATCH_SIZE];
+            for chunk in samples.chunks(BATCH_SIZE) {
+                for (value, &sample) in values.iter_mut().zip(chunk) {
+                    *value = adjust_value(self.typ, sample);
+                    sum += u64::from(*value);
+                }
+                batch_bucket_indices(ranges, &values, &mut indices);
+                for &idx in &indices[..chunk.len()] {
+                    self.buckets[idx] += 1;
+                }
+            }
+        } else {
+            for &sample in samples {
+                let value = adjust_value(self.typ, sample);
+                sum += u64::from(value);
+                self.buckets
+                    [find_bucket(self.typ, self.min, self.max, self.bits, ranges, value)] += 1;
+            }
+        }
+
+        self.sum += sum;
+        self.count += len;
+    }
+
+    /// Add each `(value, count)` pair, as if calling `accumulate` for each of them.
+    pub fn accumulate_pairs(&mut self, pairs: &[(u32, u32)]) {
+        for &(value, count) in pairs {
+            self.accumulate(value, count);
+        }
     }
 
     /// Get an iterator over this histogram's buckets.
-    pub fn buckets(&self) -> Buckets<T> {
+    pub fn buckets(&self) -> Buckets<'_> {
         Buckets {
-            histogram: self,
+            ranges: self.ranges(),
+            counts: &self.buckets,
             index: 0,
         }
     }
 
     /// Get the total sum of values recorded in this histogram.
-    pub fn sum(&self) -> u32 {
+    pub fn sum(&self) -> u64 {
         self.sum
     }
 
     /// Get the total count of values recorded in this histogram.
-    pub fn count(&self) -> u32 {
+    pub fn count(&self) -> u64 {
         self.count
     }
 
-    fn bucket(&mut self, value: u32) -> &mut u32 {
-        let mut under = 0;
-        let mut over = self.bucket_count();
-        let mut mid;
+    /// Get the name of this histogram, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.meta
+            .as_ref()
+            .and_then(|meta| meta.name.as_ref().map(|s| &s[..]))
+    }
 
-        loop {
-            mid = under + (over - under) / 2;
-            if mid == under {
-                break;
-            }
-            if self.ranges()[mid] <= value {
-                under = mid;
-            } else {
-                over = mid;
-            }
-        }
+    /// Get the unit of the values recorded in this histogram, if it has one.
+    pub fn unit(&self) -> Option<&str> {
+        self.meta
+            .as_ref()
+            .and_then(|meta| meta.unit.as_ref().map(|s| &s[..]))
+    }
 
-        &mut self.buckets[mid]
+    fn bucket(&mut self, value: u32) -> &mut u64 {
+        let idx = find_bucket(
+            self.typ,
+            self.min,
+            self.max,
+            self.bits,
+            self.ranges(),
+            value,
+        );
+        &mut self.buckets[idx]
     }
 
+    /// The start of each bucket.
+    ///
+    /// Externally provided ranges might contain a trailing sentinel, which is not part of this.
     fn ranges(&self) -> &[u32] {
-        self.ranges.as_ref()
+        &self.ranges.as_ref()[..self.bucket_count()]
+    }
+
+    /// Get a copy of this histogram's layout.
+    pub fn layout(&self) -> BucketLayout {
+        BucketLayout::from(self)
+    }
+
+    /// Add all values recorded in `other` to this histogram.
+    ///
+    /// Both histograms need to have the same type, range and bucket ranges,
+    /// but can use different storage for their ranges.
+    pub fn merge<U: AsRef<[u32]>>(&mut self, other: &Histogram<U>) -> Result<(), HistogramError> {
+        if self.typ != other.typ
+            || self.min != other.min
+            || self.max != other.max
+            || self.ranges() != other.ranges()
+        {
+            return Err(HistogramError::LayoutMismatch);
+        }
+
+        for (bucket, &count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+        Ok(())
     }
 
     /// Get a representation of this histogram suitable for persisting.
-    pub fn persisted(&self) -> PersistedHistogram<T> {
+    pub fn persisted(&self) -> PersistedHistogram<'_, T> {
         PersistedHistogram { histogram: self }
     }
 
+    /// Restore the data previously written through `persisted()`.
+    ///
+    /// Replaces all recorded data of this histogram.
+    /// Fails if the data can't be parsed or doesn't match this histogram's number of buckets.
+    pub fn restore_persisted(&mut self, json: &str) -> Result<(), HistogramError> {
+        let data: PersistedData = serde_json::from_str(json)?;
+        self.restore(&data)
+    }
+
+    /// Restore previously persisted data into this histogram.
+    ///
+    /// Replaces all recorded data of this histogram.
+    /// Fails if the data doesn't match this histogram's number of buckets.
+    pub fn restore(&mut self, data: &PersistedData) -> Result<(), HistogramError> {
+        if data.counts.len() != self.bucket_count() {
+            return Err(HistogramError::BucketCountMismatch {
+                expected: self.bucket_count(),
+                actual: data.counts.len(),
+            });
+        }
+
+        self.buckets.copy_from_slice(&data.counts);
+        self.sum = data.sum;
+        self.count = data.counts.iter().sum();
+        Ok(())
+    }
+
     /// Clear the stored data in the histogram
     pub fn clear(&mut self) {
         self.count = 0;
@@ -236,31 +619,114 @@ impl<T: AsRef<[u32]>> Histogram<T> {
         }
     }
 
+    /// Get a copy of the data currently recorded in this histogram and clear it.
+    pub fn snapshot_and_clear(&mut self) -> Snapshot {
+        let snapshot = self.snapshot();
+        self.clear();
+        snapshot
+    }
+
+    /// Get the data recorded in this histogram since the `earlier` snapshot was taken.
+    ///
+    /// The histogram itself is left untouched.
+    /// Fails like `Snapshot::diff` if the snapshot doesn't come from this histogram
+    /// or the histogram was cleared since.
+    pub fn delta_since(&self, earlier: &Snapshot) -> Result<Snapshot, HistogramError> {
+        self.snapshot().diff(earlier)
+    }
+
     /// Check if this histogram recorded any values.
     pub fn is_empty(&self) -> bool {
         self.count == 0
     }
 
+    /// Estimate the value below which `p` percent of the recorded values fall.
+    ///
+    /// `p` is clamped to the range `0..=100`.
+    /// The value is linearly interpolated within the bucket containing the percentile.
+    /// The last bucket has no upper bound, so percentiles falling into it are reported as the
+    /// start of that bucket.
+    ///
+    /// Returns `None` if no values were recorded or `p` is NaN.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        estimate_quantile(self.buckets(), p / 100.0)
+    }
+
+    /// Estimate the values at each of the given quantiles (`0.0..=1.0`).
+    ///
+    /// See `percentile` for how the values are estimated.
+    ///
+    /// Returns `None` if no values were recorded or any quantile is NaN.
+    pub fn quantiles(&self, quantiles: &[f64]) -> Option<Vec<f64>> {
+        quantiles
+            .iter()
+            .map(|&q| estimate_quantile(self.buckets(), q))
+            .collect()
+    }
+
+    /// Get a copy of the data currently recorded in this histogram.
     pub fn snapshot(&self) -> Snapshot {
         Snapshot {
+            ranges: self.ranges().into(),
             counts: self.buckets.clone(),
             sum: self.sum,
         }
     }
 }
 
+impl<T: AsRef<[u32]> + Clone> Histogram<T> {
+    /// Create an empty histogram with the same layout, sharing the ranges.
+    fn empty_like(&self) -> Histogram<T> {
+        Histogram {
+            min: self.min,
+            max: self.max,
+            ranges: self.ranges.clone(),
+            buckets: vec![0; self.bucket_count()].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            typ: self.typ,
+            bits: self.bits,
+            meta: self.meta.clone(),
+        }
+    }
+}
+
 impl Histogram<Box<[u32]>> {
     /// Create a histogram with `count` linear  buckets in the range `min` to `max`.
     ///
     /// The minimum will be at least 1.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the parameters are invalid, see `try_linear`.
     pub fn linear(min: u32, max: u32, count: u32) -> Histogram<Box<[u32]>> {
+        Self::try_linear(min, max, count).expect("invalid linear histogram")
+    }
+
+    /// Create a histogram with `count` linear  buckets in the range `min` to `max`.
+    ///
+    /// The minimum will be at least 1, the maximum at most `i32::MAX - 1`.
+    /// Fails if there are less than 3 buckets, the minimum is not below the maximum
+    /// or the range is too small to give each bucket a distinct start.
+    pub fn try_linear(
+        min: u32,
+        max: u32,
+        count: u32,
+    ) -> Result<Histogram<Box<[u32]>>, HistogramError> {
         let min = cmp::max(1, min);
-        let max = cmp::min(max, (i32::max_value()-1) as u32);
+        let max = cmp::min(max, (i32::MAX - 1) as u32);
+        if count < 3 {
+            return Err(HistogramError::InvalidBucketCount { count });
+        }
+        if min >= max {
+            return Err(HistogramError::InvalidRange { min, max });
+        }
 
         let ranges = linear_range(min, max, count);
+        validate_ranges(min, max, &ranges)?;
         let ranges = ranges.into_boxed_slice();
 
-        Histogram {
+        Ok(Histogram {
             min,
             max,
             ranges,
@@ -268,19 +734,49 @@ impl Histogram<Box<[u32]>> {
             count: 0,
             sum: 0,
             typ: Type::Linear,
-        }
+            bits: 0,
+            meta: None,
+        })
     }
 
     /// Create a histogram with `count` exponential buckets in the range `min` to `max`.
     ///
     /// The minimum will be at least 1.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the parameters are invalid, see `try_exponential`.
     pub fn exponential(min: u32, max: u32, count: u32) -> Histogram<Box<[u32]>> {
+        Self::try_exponential(min, max, count).expect("invalid exponential histogram")
+    }
+
+    /// Create a histogram with `count` exponential buckets in the range `min` to `max`.
+    ///
+    /// The minimum will be at least 1.
+    /// Fails if there are less than 3 buckets, the minimum is not below the maximum,
+    /// or the buckets above the minimum don't fit in `u32`.
+    pub fn try_exponential(
+        min: u32,
+        max: u32,
+        count: u32,
+    ) -> Result<Histogram<Box<[u32]>>, HistogramError> {
         let min = cmp::max(1, min);
+        if count < 3 {
+            return Err(HistogramError::InvalidBucketCount { count });
+        }
+        if min >= max {
+            return Err(HistogramError::InvalidRange { min, max });
+        }
+        // Every bucket after the one starting at `min` starts at least one higher.
+        if u64::from(min) + u64::from(count - 2) > u64::from(u32::MAX) {
+            return Err(HistogramError::InvalidBucketCount { count });
+        }
 
         let ranges = exponential_range(min, max, count);
+        validate_ranges(min, max, &ranges)?;
         let ranges = ranges.into_boxed_slice();
 
-        Histogram {
+        Ok(Histogram {
             min,
             max,
             ranges,
@@ -288,7 +784,53 @@ impl Histogram<Box<[u32]>> {
             count: 0,
             sum: 0,
             typ: Type::Exponential,
+            bits: 0,
+            meta: None,
+        })
+    }
+
+    /// Create a log-linear histogram keeping apart values that differ in `significant_digits` digits.
+    ///
+    /// Small values get a bucket each.
+    /// Above that every power of two is split into equally wide buckets,
+    /// so each bucket start is within `10^-significant_digits` of the values in it, relative to their size.
+    /// The buckets cover all `u32` values and recording a value takes constant time.
+    /// `LogLinearHistogram` continues the same buckets up to `u64::MAX`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `significant_digits` is invalid, see `try_log_linear`.
+    pub fn log_linear(significant_digits: u32) -> Histogram<Box<[u32]>> {
+        Self::try_log_linear(significant_digits).expect("invalid log-linear histogram")
+    }
+
+    /// Create a log-linear histogram keeping apart values that differ in `significant_digits` digits.
+    ///
+    /// Fails if `significant_digits` is not between 1 and 3.
+    pub fn try_log_linear(
+        significant_digits: u32,
+    ) -> Result<Histogram<Box<[u32]>>, HistogramError> {
+        if !(1..=MAX_SIGNIFICANT_DIGITS).contains(&significant_digits) {
+            return Err(HistogramError::InvalidPrecision {
+                digits: significant_digits,
+            });
         }
+
+        let bits = log_linear_bits(significant_digits);
+        let ranges = log_linear_range(bits).into_boxed_slice();
+        let count = ranges.len();
+
+        Ok(Histogram {
+            min: 1,
+            max: u32::MAX,
+            ranges,
+            buckets: vec![0; count].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            typ: Type::LogLinear,
+            bits,
+            meta: None,
+        })
     }
 
     /// Create a flag histogram.
@@ -311,37 +853,213 @@ impl Histogram<Box<[u32]>> {
         h
     }
 
+    /// Create a count histogram.
+    ///
+    /// This histogram type has a single counter, which every added value increments by one.
+    /// The added values themselves are ignored.
+    pub fn count_histogram() -> Histogram<Box<[u32]>> {
+        let mut h = Self::linear(1, 2, 3);
+        h.typ = Type::Count;
+        h
+    }
+
+    /// Convert into a histogram whose ranges can be shared with other histograms.
+    pub fn into_shared(self) -> Histogram<Arc<[u32]>> {
+        Histogram {
+            min: self.min,
+            max: self.max,
+            ranges: self.ranges.into(),
+            buckets: self.buckets,
+            count: self.count,
+            sum: self.sum,
+            typ: self.typ,
+            bits: self.bits,
+            meta: self.meta,
+        }
+    }
+
     /// Create a histogram over enumeratable values.
     ///
     /// An enumerated histogram consists of exactly `count` buckets.
     /// Each bucket is associated with a consecutive integer.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `count` is invalid, see `try_enumerated`.
     pub fn enumerated(count: u32) -> Histogram<Box<[u32]>> {
-        Self::linear(1, count, count + 1)
+        Self::try_enumerated(count).expect("invalid enumerated histogram")
+    }
+
+    /// Create a histogram over enumeratable values.
+    ///
+    /// Fails if `count` is less than 2 or too large.
+    pub fn try_enumerated(count: u32) -> Result<Histogram<Box<[u32]>>, HistogramError> {
+        if count < 2 || count > (i32::MAX - 1) as u32 {
+            return Err(HistogramError::InvalidBucketCount { count });
+        }
+        Self::try_linear(1, count, count + 1)
     }
 }
 
+impl Snapshot {
+    /// Get an iterator over the buckets of this snapshot.
+    pub fn buckets(&self) -> Buckets<'_> {
+        Buckets {
+            ranges: &self.ranges,
+            counts: &self.counts,
+            index: 0,
+        }
+    }
+
+    /// The start of each bucket.
+    pub fn ranges(&self) -> &[u32] {
+        &self.ranges
+    }
+
+    /// The count of each bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Get the number of buckets.
+    pub fn bucket_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Get the sum of all values recorded at the time of the snapshot.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Get the number of values recorded at the time of the snapshot.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Check if no values were recorded at the time of the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+
+    /// Get the data recorded between the `earlier` snapshot and this one.
+    ///
+    /// Fails if the snapshots have a different number of buckets or different ranges,
+    /// or with `CounterReset` if any count or the sum is lower than in the earlier snapshot.
+    pub fn diff(&self, earlier: &Snapshot) -> Result<Snapshot, HistogramError> {
+        if self.counts.len() != earlier.counts.len() {
+            return Err(HistogramError::BucketCountMismatch {
+                expected: self.counts.len(),
+                actual: earlier.counts.len(),
+            });
+        }
+        if self.ranges != earlier.ranges {
+            return Err(HistogramError::LayoutMismatch);
+        }
+
+        let counts = self
+            .counts
+            .iter()
+            .zip(earlier.counts.iter())
+            .map(|(&now, &before)| now.checked_sub(before))
+            .collect::<Option<Box<[u64]>>>();
+        match (counts, self.sum.checked_sub(earlier.sum)) {
+            (Some(counts), Some(sum)) => Ok(Snapshot {
+                ranges: self.ranges.clone(),
+                counts,
+                sum,
+            }),
+            _ => Err(HistogramError::CounterReset),
+        }
+    }
+
+    /// Estimate the value below which `p` percent of the values in this snapshot fall.
+    ///
+    /// See `Histogram::percentile`.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        estimate_quantile(self.buckets(), p / 100.0)
+    }
+
+    /// Estimate the values at each of the given quantiles (`0.0..=1.0`).
+    ///
+    /// See `Histogram::quantiles`.
+    pub fn quantiles(&self, quantiles: &[f64]) -> Option<Vec<f64>> {
+        quantiles
+            .iter()
+            .map(|&q| estimate_quantile(self.buckets(), q))
+            .collect()
+    }
+}
+
+/// Estimate the value at `quantile` by interpolating within the bucket it falls into.
+fn estimate_quantile(buckets: Buckets<'_>, quantile: f64) -> Option<f64> {
+    let total = buckets.counts.iter().sum::<u64>() as f64;
+    let weights = buckets.map(|b| (u64::from(b.start), unbounded_end(b.end), b.count as f64));
+    estimate_weighted_quantile(weights, total, quantile)
+}
+
+/// Widen the end of a bucket, keeping the last bucket without an upper bound.
+fn unbounded_end(end: u32) -> u64 {
+    if end == u32::MAX {
+        u64::MAX
+    } else {
+        u64::from(end)
+    }
+}
+
+/// Estimate the value at `quantile` of buckets given as `(start, end, weight)`,
+/// whose weights add up to `total`.
+///
+/// The last bucket ends at `u64::MAX` and has no upper bound to interpolate towards.
+fn estimate_weighted_quantile<I>(buckets: I, total: f64, quantile: f64) -> Option<f64>
+where
+    I: Iterator<Item = (u64, u64, f64)>,
+{
+    if quantile.is_nan() {
+        return None;
+    }
+    let rank = quantile.clamp(0.0, 1.0) * total;
+
+    let mut seen = 0.0;
+    for (start, end, weight) in buckets.filter(|b| b.2 > 0.0) {
+        let before = seen;
+        seen += weight;
+        if seen < rank {
+            continue;
+        }
+
+        if end == u64::MAX {
+            return Some(start as f64);
+        }
+        let fraction = (rank - before) / weight;
+        return Some(start as f64 + fraction * (end - start) as f64);
+    }
+
+    None
+}
+
 /// An iterator over the buckets in a histogram.
 #[derive(Debug, Clone)]
-pub struct Buckets<'a, T: 'a + AsRef<[u32]>> {
-    histogram: &'a Histogram<T>,
+pub struct Buckets<'a> {
+    ranges: &'a [u32],
+    counts: &'a [u64],
     index: usize,
 }
 
-impl<'a, T: AsRef<[u32]>> Iterator for Buckets<'a, T> {
+impl<'a> Iterator for Buckets<'a> {
     type Item = Bucket;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= self.histogram.bucket_count() {
+        if self.index >= self.counts.len() {
             return None;
         }
-        let start = self.histogram.ranges()[self.index];
-        let end = if self.index + 1 == self.histogram.bucket_count() {
-            ::std::u32::MAX
+        let start = self.ranges[self.index];
+        let end = if self.index + 1 == self.counts.len() {
+            u32::MAX
         } else {
-            self.histogram.ranges()[self.index + 1]
+            self.ranges[self.index + 1]
         };
 
-        let count = self.histogram.buckets[self.index];
+        let count = self.counts[self.index];
         self.index += 1;
 
         Some(Bucket { start, end, count })
@@ -353,12 +1071,12 @@ impl<'a, T: AsRef<[u32]>> Iterator for Buckets<'a, T> {
 pub struct Bucket {
     start: u32,
     end: u32,
-    count: u32,
+    count: u64,
 }
 
 impl Bucket {
     /// The number of samples in this bucket's range.
-    pub fn count(&self) -> u32 {
+    pub fn count(&self) -> u64 {
         self.count
     }
 
@@ -374,82 +1092,98 @@ impl Bucket {
 }
 
 impl fmt::Debug for Bucket {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Bucket {{ {}..{} }}", self.start, self.end)
     }
 }
 
 impl<T: AsRef<[u32]>> fmt::Display for Histogram<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use std::fmt::Write;
-
-        let count = self.count;
-        writeln!(f, "# Number of samples = {}", count)?;
-        if count == 0 {
-            return Ok(());
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = self.name() {
+            writeln!(f, "# Name = {}", name)?;
+        }
+        if let Some(unit) = self.unit() {
+            writeln!(f, "# Unit = {}", unit)?;
         }
 
-        let max_bucket_count = self.buckets().map(|b| b.count()).fold(0, cmp::max);
-
-        const WIDTH: u32 = 50;
-        let count_per_char = cmp::max(max_bucket_count / WIDTH, 1);
-
-        writeln!(f, "# Each ∎ is a count of {}", count_per_char)?;
-        writeln!(f, "#")?;
-
-        let mut count_str = String::new();
-
-        let widest_count = self.buckets().fold(0, |n, b| {
-            count_str.clear();
-            write!(&mut count_str, "{}", b.count()).unwrap();
-            cmp::max(n, count_str.len())
-        });
-
-        let mut end_str = String::new();
-        let widest_range = self.buckets().fold(0, |n, b| {
-            end_str.clear();
-            if b.end() == ::std::u32::MAX {
-                cmp::max(n, 3)
-            } else {
-                write!(&mut end_str, "{}", b.end()).unwrap();
-                cmp::max(n, end_str.len())
-            }
-        });
+        write_rows(f, self.count, &bucket_rows(self.buckets()))
+    }
+}
 
-        let mut start_str = String::with_capacity(widest_range);
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_rows(f, self.count(), &bucket_rows(self.buckets()))
+    }
+}
 
-        for bucket in self.buckets() {
-            start_str.clear();
-            write!(&mut start_str, "{}", bucket.start()).unwrap();
-            for _ in 0..widest_range - start_str.len() {
-                start_str.insert(0, ' ');
-            }
+/// Label each bucket with its range, aligning the starts and ends.
+fn bucket_rows(buckets: Buckets<'_>) -> Vec<(String, u64)> {
+    let widest_range = buckets.clone().fold(0, |n, b| {
+        if b.end() == u32::MAX {
+            cmp::max(n, 3)
+        } else {
+            cmp::max(n, b.end().to_string().len())
+        }
+    });
 
-            end_str.clear();
-            if bucket.end() == ::std::u32::MAX {
-                write!(&mut end_str, "INF").unwrap();
+    buckets
+        .map(|bucket| {
+            let end = if bucket.end() == u32::MAX {
+                String::from("INF")
             } else {
-                write!(&mut end_str, "{}", bucket.end()).unwrap();
-            }
-            for _ in 0..widest_range - end_str.len() {
-                end_str.insert(0, ' ');
-            }
+                bucket.end().to_string()
+            };
+            let label = format!(
+                "{:>width$} .. {:>width$}",
+                bucket.start(),
+                end,
+                width = widest_range
+            );
+            (label, bucket.count())
+        })
+        .collect()
+}
 
-            count_str.clear();
-            write!(&mut count_str, "{}", bucket.count()).unwrap();
-            for _ in 0..widest_count - count_str.len() {
-                count_str.insert(0, ' ');
-            }
+/// Render a text histogram of `count` samples, with one line per `(label, count)` row.
+fn write_rows(f: &mut fmt::Formatter<'_>, count: u64, rows: &[(String, u64)]) -> fmt::Result {
+    writeln!(f, "# Number of samples = {}", count)?;
+    if count == 0 {
+        return Ok(());
+    }
 
-            write!(f, "{} .. {} [ {} ]: ", start_str, end_str, count_str)?;
-            for _ in 0..bucket.count() / count_per_char {
-                write!(f, "∎")?;
-            }
-            writeln!(f)?;
+    let max_bucket_count = rows.iter().map(|row| row.1).fold(0, cmp::max);
+
+    const WIDTH: u64 = 50;
+    let count_per_char = cmp::max(max_bucket_count / WIDTH, 1);
+
+    writeln!(f, "# Each ∎ is a count of {}", count_per_char)?;
+    writeln!(f, "#")?;
+
+    let widest_label = rows
+        .iter()
+        .map(|row| row.0.chars().count())
+        .fold(0, cmp::max);
+    let widest_count = rows
+        .iter()
+        .map(|row| row.1.to_string().len())
+        .fold(0, cmp::max);
+
+    for (label, count) in rows {
+        write!(
+            f,
+            "{:>label_width$} [ {:>count_width$} ]: ",
+            label,
+            count,
+            label_width = widest_label,
+            count_width = widest_count
+        )?;
+        for _ in 0..count / count_per_char {
+            write!(f, "∎")?;
         }
-
-        Ok(())
+        writeln!(f)?;
     }
+
+    Ok(())
 }
 
 /// Packed representation of a histogram for serialization
@@ -469,12 +1203,40 @@ impl<'a, T: AsRef<[u32]>> Serialize for PersistedHistogram<'a, T> {
     }
 }
 
+/// Data of a histogram as written by `PersistedHistogram`.
+///
+/// Can be restored into a histogram with the same buckets using `Histogram::restore`.
+#[derive(Debug, Deserialize)]
+pub struct PersistedData {
+    sum: u64,
+    counts: Box<[u64]>,
+}
+
+impl PersistedData {
+    /// The total sum of the persisted values.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// The persisted counts per bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+}
+
 impl<T: AsRef<[u32]>> Serialize for Histogram<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Histogram", 5)?;
+        let len = 5 + self.name().iter().count() + self.unit().iter().count();
+        let mut state = serializer.serialize_struct("Histogram", len)?;
+        if let Some(name) = self.name() {
+            state.serialize_field("name", name)?;
+        }
+        if let Some(unit) = self.unit() {
+            state.serialize_field("unit", unit)?;
+        }
         state.serialize_field("range", &[self.min, self.max])?;
         state.serialize_field("bucket_count", &self.bucket_count())?;
         state.serialize_field("histogram_type", &(self.typ as u32))?;
@@ -488,7 +1250,162 @@ impl<T: AsRef<[u32]>> Serialize for Histogram<T> {
     }
 }
 
+/// The packed representation as written by `impl Serialize for Histogram`.
+#[derive(Deserialize)]
+struct PackedHistogram {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    unit: Option<String>,
+    range: [u32; 2],
+    bucket_count: u32,
+    histogram_type: u32,
+    values: BTreeMap<String, u64>,
+    sum: u64,
+}
+
+/// The representation as written by the derived `Serialize` for `Snapshot`.
+#[derive(Deserialize)]
+struct PackedSnapshot {
+    ranges: Box<[u32]>,
+    counts: Box<[u64]>,
+    sum: u64,
+}
+
+impl<'de> Deserialize<'de> for Snapshot {
+    /// Restore a snapshot, checking that there is one count per bucket.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let packed = PackedSnapshot::deserialize(deserializer)?;
+        if packed.ranges.is_empty() {
+            return Err(de::Error::custom(HistogramError::InvalidBucketCount {
+                count: 0,
+            }));
+        }
+        if packed.ranges.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(de::Error::custom(HistogramError::NonMonotonicRanges));
+        }
+        if packed.ranges.len() != packed.counts.len() {
+            return Err(de::Error::custom(HistogramError::BucketCountMismatch {
+                expected: packed.ranges.len(),
+                actual: packed.counts.len(),
+            }));
+        }
+
+        Ok(Snapshot {
+            ranges: packed.ranges,
+            counts: packed.counts,
+            sum: packed.sum,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Histogram<Box<[u32]>> {
+    /// Rebuild a histogram from its packed representation.
+    ///
+    /// The ranges are recomputed from the histogram type, range and bucket count.
+    /// Histograms of type `External` can't be restored this way, as their ranges are not part of
+    /// the serialized data.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        PackedHistogram::deserialize(deserializer)?.unpack()
+    }
+}
+
+impl PackedHistogram {
+    /// Rebuild the histogram this representation was written from.
+    fn unpack<E: de::Error>(self) -> Result<Histogram<Box<[u32]>>, E> {
+        let [min, max] = self.range;
+        let bucket_count = self.bucket_count;
+
+        let mut hist = match Type::from_u32(self.histogram_type) {
+            Some(Type::Exponential) => {
+                Histogram::try_exponential(min, max, bucket_count).map_err(de::Error::custom)?
+            }
+            Some(typ @ Type::Linear) | Some(typ @ Type::Categorical) => {
+                let mut h =
+                    Histogram::try_linear(min, max, bucket_count).map_err(de::Error::custom)?;
+                h.typ = typ;
+                h
+            }
+            Some(Type::Boolean) => Histogram::boolean(),
+            Some(Type::Flag) => Histogram::flag(),
+            Some(Type::Count) => Histogram::count_histogram(),
+            Some(Type::LogLinear) => match log_linear_digits(bucket_count as usize, 32) {
+                Some(digits) => Histogram::log_linear(digits),
+                None => {
+                    return Err(de::Error::custom(format_args!(
+                        "{} buckets do not match any log-linear histogram",
+                        bucket_count
+                    )))
+                }
+            },
+            Some(Type::External) => {
+                return Err(de::Error::custom(
+                    "can't restore the ranges of an external histogram",
+                ))
+            }
+            Some(Type::Sparse) => {
+                return Err(de::Error::custom(
+                    "can't restore the exact values of a sparse histogram into buckets",
+                ))
+            }
+            None => {
+                return Err(de::Error::custom(format_args!(
+                    "unknown histogram type {}",
+                    self.histogram_type
+                )))
+            }
+        };
+
+        if hist.min != min || hist.max != max || hist.bucket_count() != bucket_count as usize {
+            return Err(de::Error::custom(format_args!(
+                "range {}..{} with {} buckets does not match histogram type {:?}",
+                min, max, bucket_count, hist.typ
+            )));
+        }
+
+        let mut seen = vec![false; hist.bucket_count()];
+        for (start, &count) in &self.values {
+            let start = start.parse::<u32>().map_err(de::Error::custom)?;
+            let idx = match hist.ranges().binary_search(&start) {
+                Ok(idx) => idx,
+                Err(_) => {
+                    return Err(de::Error::custom(format_args!(
+                        "{} is not the start of a bucket",
+                        start
+                    )))
+                }
+            };
+            // Keys like "1" and "01" name the same bucket.
+            if seen[idx] {
+                return Err(de::Error::custom(format_args!(
+                    "bucket {} is given more than once",
+                    start
+                )));
+            }
+            seen[idx] = true;
+            hist.buckets[idx] = count;
+            hist.count += count;
+        }
+        hist.sum = self.sum;
+        if self.name.is_some() || self.unit.is_some() {
+            hist.meta = Some(Box::new(Metadata {
+                name: self.name,
+                unit: self.unit,
+            }));
+        }
+
+        Ok(hist)
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::identity_op)]
 mod tests {
     use super::*;
 
@@ -613,4 +1530,548 @@ mod tests {
         assert_eq!(1, h.count());
         assert_eq!(5, h.sum());
     }
+
+    #[test]
+    fn deserialize_packed() {
+        let mut h = Histogram::exponential(1, 500, 10);
+        h.add(0);
+        h.add(14);
+        h.add(14);
+        h.add(450);
+
+        let serialized = serde_json::to_string(&h).unwrap();
+        let restored: Histogram<Box<[u32]>> = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(h.count(), restored.count());
+        assert_eq!(h.sum(), restored.sum());
+        assert_eq!(h.ranges(), restored.ranges());
+        assert_eq!(h.buckets, restored.buckets);
+        assert_eq!(serialized, serde_json::to_string(&restored).unwrap());
+
+        let empty = Histogram::linear(1, 500, 10);
+        let serialized = serde_json::to_string(&empty).unwrap();
+        let restored: Histogram<Box<[u32]>> = serde_json::from_str(&serialized).unwrap();
+        assert!(restored.is_empty());
+    }
+
+    #[test]
+    fn deserialize_packed_mismatch() {
+        let json =
+            r#"{"range":[1,500],"bucket_count":10,"histogram_type":0,"values":{"3":1},"sum":3}"#;
+        let err = serde_json::from_str::<Histogram<Box<[u32]>>>(json).unwrap_err();
+        assert!(
+            err.to_string().contains("3 is not the start of a bucket"),
+            "unexpected error: {}",
+            err
+        );
+
+        let json = r#"{"range":[1,2],"bucket_count":3,"histogram_type":6,"values":{},"sum":0}"#;
+        assert!(serde_json::from_str::<Histogram<Box<[u32]>>>(json).is_err());
+
+        let json = r#"{"range":[1,500],"bucket_count":10,"histogram_type":0,"values":{"1":5,"01":1},"sum":6}"#;
+        let err = serde_json::from_str::<Histogram<Box<[u32]>>>(json).unwrap_err();
+        assert!(
+            err.to_string().contains("bucket 1 is given more than once"),
+            "unexpected error: {}",
+            err
+        );
+        let json = r#"{"range":[4294967290,4294967295],"bucket_count":10,"histogram_type":0,"values":{},"sum":0}"#;
+        assert!(serde_json::from_str::<Histogram<Box<[u32]>>>(json).is_err());
+    }
+
+    #[test]
+    fn restore_persisted() {
+        let mut h = Histogram::exponential(1, 500, 10);
+        h.add(1);
+        h.add(14);
+        h.add(450);
+        let persisted = serde_json::to_string(&h.persisted()).unwrap();
+
+        let mut restored = Histogram::exponential(1, 500, 10);
+        restored.add(7);
+        restored.restore_persisted(&persisted).unwrap();
+
+        assert_eq!(3, restored.count());
+        assert_eq!(1 + 14 + 450, restored.sum());
+        assert_eq!(h.buckets, restored.buckets);
+
+        let mut other = Histogram::linear(1, 500, 5);
+        match other.restore_persisted(&persisted) {
+            Err(HistogramError::BucketCountMismatch { expected, actual }) => {
+                assert_eq!(5, expected);
+                assert_eq!(10, actual);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(other.is_empty());
+
+        match other.restore_persisted("{\"sum\": 3}") {
+            Err(HistogramError::Json(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = Histogram::exponential(1, 500, 10);
+        a.add(1);
+        a.add(14);
+        let mut b = Histogram::exponential(1, 500, 10);
+        b.add(14);
+        b.add(450);
+
+        a.merge(&b).unwrap();
+        assert_eq!(4, a.count());
+        assert_eq!(1 + 14 + 14 + 450, a.sum());
+        let expected_counts = [0, 1, 0, 0, 2, 0, 0, 0, 1, 0];
+        assert_eq!(&expected_counts[..], &a.buckets[..]);
+
+        let linear = Histogram::linear(1, 500, 10);
+        match a.merge(&linear) {
+            Err(HistogramError::LayoutMismatch) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(4, a.count());
+    }
+
+    #[test]
+    fn merge_static() {
+        static RANGES: [i32; 6] = [0, 1, 2, 4, 8, i32::MAX];
+
+        let mut h = Histogram::factory_get(1, 8, vec![0, 1, 2, 4, 8].into_boxed_slice());
+        h.add(3);
+
+        unsafe {
+            let s = ffi::histogram_factory_get(1, 8, 5, RANGES.as_ptr());
+            ffi::histogram_add(s, 5);
+            ffi::histogram_add(s, 10);
+            h.merge(&*s).unwrap();
+            ffi::histogram_free(s);
+        }
+
+        assert_eq!(3, h.count());
+        assert_eq!(3 + 5 + 10, h.sum());
+        assert_eq!(&[0, 0, 1, 1, 1][..], &h.buckets[..]);
+    }
+
+    #[test]
+    fn percentile() {
+        let mut h = Histogram::linear(1, 100, 101);
+        assert_eq!(None, h.percentile(50.0));
+
+        for i in 1..=100 {
+            h.add(i);
+        }
+
+        assert_eq!(Some(1.0), h.percentile(0.0));
+        // Every value covers its whole bucket, `50` ranges from 50 to 51.
+        assert_eq!(Some(51.0), h.percentile(50.0));
+        assert_eq!(Some(96.0), h.percentile(95.0));
+        assert_eq!(Some(100.0), h.percentile(100.0));
+        assert_eq!(None, h.percentile(f64::NAN));
+        assert_eq!(None, h.quantiles(&[0.5, f64::NAN]));
+        assert_eq!(
+            Some(vec![51.0, 96.0, 100.0]),
+            h.quantiles(&[0.5, 0.95, 1.0])
+        );
+
+        let snapshot = h.snapshot();
+        assert_eq!(h.percentile(99.0), snapshot.percentile(99.0));
+        assert_eq!(h.quantiles(&[0.1, 0.9]), snapshot.quantiles(&[0.1, 0.9]));
+    }
+
+    #[test]
+    fn percentile_interpolation() {
+        let mut h = Histogram::factory_get(1, 100, vec![0, 10, 20, 100].into_boxed_slice());
+        for _ in 0..4 {
+            h.add(12);
+        }
+        h.add(500);
+
+        assert_eq!(Some(12.5), h.percentile(20.0));
+        assert_eq!(Some(15.0), h.percentile(40.0));
+        assert_eq!(Some(20.0), h.percentile(80.0));
+        // The last bucket is open-ended and reports its start.
+        assert_eq!(Some(100.0), h.percentile(99.0));
+    }
+
+    #[test]
+    fn large_sum() {
+        let mut h = Histogram::exponential(1, 60000, 20);
+        for _ in 0..5000 {
+            h.add(1_000_000);
+        }
+        h.accumulate(4_000_000_000, 4);
+
+        assert_eq!(5004, h.count());
+        assert_eq!(5000 * 1_000_000 + 4 * 4_000_000_000, h.sum());
+        assert_eq!(5004, h.snapshot().counts.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn accumulate() {
+        let mut h = Histogram::linear(1, 500, 10);
+        h.accumulate(14, 3);
+        h.accumulate(450, 2);
+
+        assert_eq!(5, h.count());
+        assert_eq!(3 * 14 + 2 * 450, h.sum());
+        assert_eq!(h.count(), h.buckets().map(|b| b.count()).sum::<u64>());
+
+        let mut batched = Histogram::linear(1, 500, 10);
+        batched.accumulate_pairs(&[(14, 3), (450, 2)]);
+        assert_eq!(h.buckets, batched.buckets);
+        assert_eq!(h.sum(), batched.sum());
+
+        let mut single = Histogram::linear(1, 500, 10);
+        let mut many = Histogram::linear(1, 500, 10);
+        let samples = [0, 1, 14, 450, 700, u32::MAX];
+        for &sample in &samples {
+            single.add(sample);
+        }
+        many.add_many(&samples);
+        assert_eq!(single.buckets, many.buckets);
+        assert_eq!(single.count(), many.count());
+        assert_eq!(single.sum(), many.sum());
+    }
+
+    #[test]
+    fn count() {
+        let mut h = Histogram::count_histogram();
+        h.add(0);
+        h.add(7);
+        h.add(500);
+        h.accumulate(3, 2);
+
+        assert_eq!(5, h.count());
+        assert_eq!(5, h.sum());
+        assert_eq!(&[5, 0, 0][..], &h.buckets[..]);
+
+        let serialized = serde_json::to_string(&h).unwrap();
+        assert_eq!(
+            r#"{"range":[1,2],"bucket_count":3,"histogram_type":4,"values":{"0":5,"1":0},"sum":5}"#,
+            serialized
+        );
+        let restored: Histogram<Box<[u32]>> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(Type::Count, restored.typ);
+        assert_eq!(5, restored.count());
+    }
+
+    #[test]
+    fn snapshot_and_clear() {
+        let mut h = Histogram::exponential(1, 500, 10);
+        h.add(1);
+        h.add(14);
+
+        let snapshot = h.snapshot_and_clear();
+        assert_eq!(&[0, 1, 0, 0, 1, 0, 0, 0, 0, 0][..], &snapshot.counts[..]);
+        assert_eq!(15, snapshot.sum);
+        assert!(h.is_empty());
+        assert_eq!(0, h.sum());
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut h = Histogram::linear(1, 10, 4);
+        h.add(2);
+        h.add(7);
+        h.add(7);
+        let snapshot = h.snapshot();
+
+        assert_eq!(&[0, 1, 5, 10][..], snapshot.ranges());
+        assert_eq!(&[0, 1, 2, 0][..], snapshot.counts());
+        assert_eq!(4, snapshot.bucket_count());
+        assert_eq!(3, snapshot.count());
+        assert_eq!(16, snapshot.sum());
+        assert!(!snapshot.is_empty());
+        let buckets = snapshot
+            .buckets()
+            .map(|b| (b.start(), b.end(), b.count()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(0, 1, 0), (1, 5, 1), (5, 10, 2), (10, u32::MAX, 0)],
+            buckets
+        );
+        assert_eq!(h.to_string(), snapshot.to_string());
+
+        let serialized = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            r#"{"ranges":[0,1,5,10],"counts":[0,1,2,0],"sum":16}"#,
+            serialized
+        );
+        let restored: Snapshot = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(snapshot, restored);
+
+        let invalid = vec![
+            r#"{"ranges":[0,1,5],"counts":[0,1,2,0],"sum":16}"#,
+            r#"{"ranges":[0,5,1,10],"counts":[0,1,2,0],"sum":16}"#,
+            r#"{"ranges":[],"counts":[],"sum":0}"#,
+        ];
+        for data in invalid {
+            assert!(serde_json::from_str::<Snapshot>(data).is_err(), "{}", data);
+        }
+    }
+
+    #[test]
+    fn delta_since() {
+        let mut h = Histogram::linear(1, 10, 4);
+        h.add(2);
+        h.add(7);
+        let earlier = h.snapshot();
+        h.add(7);
+        h.add(9);
+
+        let delta = h.delta_since(&earlier).unwrap();
+        assert_eq!(&[0, 0, 2, 0][..], delta.counts());
+        assert_eq!(16, delta.sum());
+        assert_eq!(4, h.count());
+        assert_eq!(delta, h.snapshot().diff(&earlier).unwrap());
+        assert!(h.delta_since(&h.snapshot()).unwrap().is_empty());
+
+        match earlier.diff(&h.snapshot()) {
+            Err(HistogramError::CounterReset) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        h.clear();
+        h.add(1);
+        h.add(1);
+        h.add(1);
+        match h.delta_since(&earlier) {
+            Err(HistogramError::CounterReset) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let other = Histogram::exponential(1, 10, 4).snapshot();
+        match h.delta_since(&other) {
+            Err(HistogramError::LayoutMismatch) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let other = Histogram::linear(1, 10, 5).snapshot();
+        match h.delta_since(&other) {
+            Err(HistogramError::BucketCountMismatch {
+                expected: 4,
+                actual: 5,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        match Histogram::try_linear(1, 500, 2) {
+            Err(HistogramError::InvalidBucketCount { count: 2 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_exponential(500, 1, 10) {
+            Err(HistogramError::InvalidRange { min: 500, max: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_exponential(4_294_967_290, u32::MAX, 10) {
+            Err(HistogramError::InvalidBucketCount { count: 10 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_linear(1, 5, 10) {
+            Err(HistogramError::NonMonotonicRanges) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_enumerated(0) {
+            Err(HistogramError::InvalidBucketCount { count: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_from_ranges(1, 10, vec![0, 5, 5, 10]) {
+            Err(HistogramError::NonMonotonicRanges) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Histogram::try_from_ranges(1, 10, vec![]) {
+            Err(HistogramError::InvalidBucketCount { count: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        assert!(Histogram::try_enumerated(2).is_ok());
+        assert!(Histogram::try_from_ranges(1, 10, vec![0, 1, 5, 10]).is_ok());
+    }
+
+    #[test]
+    fn wide_linear_ranges() {
+        let mut h = Histogram::try_linear(1, 2_000_000_000, 10).unwrap();
+        assert_eq!(
+            &[0, 1, 250_000_000, 500_000_000, 750_000_000, 1_000_000_000][..],
+            &h.ranges()[..6]
+        );
+        assert_eq!(2_000_000_000, h.ranges()[9]);
+        h.add(1_999_999_999);
+        assert_eq!(1, h.buckets[8]);
+
+        let h = Histogram::try_enumerated(100_000).unwrap();
+        assert_eq!(100_001, h.bucket_count());
+        let expected = (0..=100_000).collect::<Vec<u32>>();
+        assert_eq!(&expected[..], h.ranges());
+    }
+
+    #[test]
+    fn ffi_invalid_ranges() {
+        static RANGES: [i32; 4] = [0, 1, 2, 4];
+        static UNORDERED: [i32; 4] = [0, 2, 1, i32::MAX];
+        static SENTINEL: [i32; 1] = [i32::MAX];
+
+        unsafe {
+            assert!(ffi::histogram_factory_get(1, 4, 0, SENTINEL.as_ptr()).is_null());
+            assert!(ffi::histogram_factory_get(1, 4, 3, RANGES.as_ptr()).is_null());
+            assert!(ffi::histogram_factory_get(1, 4, 3, UNORDERED.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn log_linear() {
+        let bucket_counts = (1..=3)
+            .map(|digits| Histogram::log_linear(digits).bucket_count())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![464, 3328, 23552], bucket_counts);
+
+        let mut h = Histogram::log_linear(2);
+        assert_eq!(Type::LogLinear, h.typ);
+        for value in 0..256 {
+            assert_eq!(
+                value as usize,
+                find_bucket(h.typ, h.min, h.max, h.bits, h.ranges(), value)
+            );
+        }
+
+        let samples = (0..1000u32)
+            .map(|i| i.wrapping_mul(2_654_435_761) >> (i % 32))
+            .chain(vec![u32::MAX]);
+        for value in samples {
+            let start = h.ranges()[find_bucket(h.typ, h.min, h.max, h.bits, h.ranges(), value)];
+            assert!(start <= value);
+            assert!(
+                f64::from(value - start) <= f64::from(value) / 100.0,
+                "{} recorded in bucket starting at {}",
+                value,
+                start
+            );
+        }
+
+        for i in 1..=1000 {
+            h.add(i * 1000);
+        }
+        assert_eq!(1000, h.count());
+        let p50 = h.percentile(50.0).unwrap();
+        assert!((p50 - 500_000.0).abs() <= 5000.0, "{}", p50);
+
+        let serialized = serde_json::to_string(&h).unwrap();
+        assert!(
+            serialized
+                .starts_with(r#"{"range":[1,4294967295],"bucket_count":3328,"histogram_type":7,"#),
+            "{}",
+            serialized
+        );
+        let restored: Histogram<Box<[u32]>> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(Type::LogLinear, restored.typ);
+        assert_eq!(h.bits, restored.bits);
+        assert_eq!(h.buckets, restored.buckets);
+        assert_eq!(h.sum(), restored.sum());
+
+        for &digits in &[0, 4] {
+            match Histogram::try_log_linear(digits) {
+                Err(HistogramError::InvalidPrecision { digits: d }) => assert_eq!(digits, d),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn log_linear_largest_values() {
+        let values = [3, 700, 123_456, u32::MAX];
+        let mut single = Histogram::log_linear(2);
+        for &value in &values {
+            single.add(value);
+        }
+        let mut many = Histogram::log_linear(2);
+        many.add_many(&values);
+
+        // Log-linear histograms record the largest values as they are.
+        for h in &[single, many] {
+            assert_eq!(u64::from(u32::MAX) + 124_159, h.sum());
+            assert_eq!(Some(&1), h.buckets.last());
+        }
+    }
+
+    #[test]
+    fn direct_bucket_lookup() {
+        let layouts = vec![
+            Histogram::linear(1, 500, 10),
+            Histogram::linear(7, 100_000, 37),
+            Histogram::enumerated(20),
+            Histogram::boolean(),
+            Histogram::exponential(1, 500, 10),
+            Histogram::exponential(1, 60000, 50),
+            Histogram::exponential(10, 10_000_000, 100),
+            Histogram::log_linear(1),
+            Histogram::log_linear(3),
+        ];
+
+        for h in &layouts {
+            let ranges = h.ranges();
+            let samples = ranges
+                .iter()
+                .flat_map(|&r| vec![r.saturating_sub(1), r, r + 1])
+                .chain((0..2000).map(|i| i * 7919))
+                .chain(vec![u32::MAX - 1, u32::MAX]);
+            for sample in samples {
+                assert_eq!(
+                    bucket_index(ranges, sample),
+                    find_bucket(h.typ, h.min, h.max, h.bits, ranges, sample),
+                    "{:?} {}..{} with {} buckets, sample {}",
+                    h.typ,
+                    h.min,
+                    h.max,
+                    h.bucket_count(),
+                    sample
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn add_many_matches_add() {
+        let samples = (0..10_000u32)
+            .map(|i| {
+                let scrambled = i.wrapping_mul(2_654_435_761);
+                match i % 4 {
+                    0 => scrambled % 100,
+                    1 => scrambled % 100_000,
+                    2 => scrambled,
+                    _ => i,
+                }
+            })
+            .collect::<Vec<u32>>();
+
+        let layouts = vec![
+            Histogram::linear(1, 500, 10),
+            Histogram::enumerated(20),
+            Histogram::boolean(),
+            Histogram::count_histogram(),
+            Histogram::exponential(1, 60000, 50),
+            Histogram::exponential(1, 500, 3),
+            Histogram::log_linear(2),
+            Histogram::factory_get(1, 100, vec![0, 10, 20, 100].into_boxed_slice()),
+            Histogram::factory_get(5, 6, vec![5].into_boxed_slice()),
+        ];
+
+        for layout in layouts {
+            let mut single = layout.empty_like();
+            let mut many = layout.empty_like();
+            for &sample in &samples {
+                single.add(sample);
+            }
+            // Uneven slices to exercise partial batches.
+            for chunk in samples.chunks(1000 + 7) {
+                many.add_many(chunk);
+            }
+
+            assert_eq!(single.buckets, many.buckets, "{:?}", layout.typ);
+            assert_eq!(single.count(), many.count());
+            assert_eq!(single.sum(), many.sum());
+        }
+    }
 }
@@ -0,0 +1,460 @@
+//! Log-linear histograms over the full range of `u64` values.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use super::{
+    estimate_weighted_quantile, log_linear_bits, log_linear_bucket_count, log_linear_digits,
+    log_linear_index, log_linear_start, write_rows, HistogramError, Type, MAX_SIGNIFICANT_DIGITS,
+};
+
+/// A log-linear histogram of `u64` values.
+///
+/// It has the same layout as `Histogram::log_linear`, continued up to `u64::MAX`:
+/// values below `2^bits` get a bucket each, and every larger power of two is split
+/// into equally wide buckets, so each bucket start is within `10^-significant_digits`
+/// of the values in it, relative to their size.
+/// Finding the bucket of a value takes a constant number of steps.
+///
+/// ```rust
+/// # use histogram::LogLinearHistogram;
+/// let mut latency_ns = LogLinearHistogram::new(2);
+/// latency_ns.add(1_500);
+/// latency_ns.add(90_000_000_000);
+///
+/// assert_eq!(2, latency_ns.count());
+/// let p99 = latency_ns.percentile(99.0).unwrap();
+/// assert!((p99 - 90e9).abs() <= 90e9 / 100.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct LogLinearHistogram {
+    significant_digits: u32,
+    /// Bits of precision, derived from the significant digits once.
+    bits: u32,
+    buckets: Box<[u64]>,
+
+    count: u64,
+    sum: u64,
+}
+
+impl LogLinearHistogram {
+    /// Create a histogram keeping apart values that differ in `significant_digits` digits.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `significant_digits` is invalid, see `try_new`.
+    pub fn new(significant_digits: u32) -> LogLinearHistogram {
+        Self::try_new(significant_digits).expect("invalid log-linear histogram")
+    }
+
+    /// Create a histogram keeping apart values that differ in `significant_digits` digits.
+    ///
+    /// Fails if `significant_digits` is not between 1 and 3.
+    pub fn try_new(significant_digits: u32) -> Result<LogLinearHistogram, HistogramError> {
+        if !(1..=MAX_SIGNIFICANT_DIGITS).contains(&significant_digits) {
+            return Err(HistogramError::InvalidPrecision {
+                digits: significant_digits,
+            });
+        }
+
+        let bits = log_linear_bits(significant_digits);
+        Ok(LogLinearHistogram {
+            significant_digits,
+            bits,
+            buckets: vec![0; log_linear_bucket_count(bits, 64)].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+        })
+    }
+
+    /// The number of significant digits kept apart by the buckets.
+    pub fn significant_digits(&self) -> u32 {
+        self.significant_digits
+    }
+
+    /// Get the number of buckets in this histogram.
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Add a single value to this histogram.
+    pub fn add(&mut self, value: u64) {
+        self.accumulate(value, 1);
+    }
+
+    /// Add `count` number of values.
+    ///
+    /// The sum saturates at `u64::MAX`.
+    pub fn accumulate(&mut self, value: u64, count: u32) {
+        let count = u64::from(count);
+        self.buckets[log_linear_index(self.bits, value)] += count;
+        self.count += count;
+        self.sum = self.sum.saturating_add(value.saturating_mul(count));
+    }
+
+    /// Get an iterator over this histogram's buckets.
+    pub fn buckets(&self) -> LogLinearBuckets<'_> {
+        LogLinearBuckets {
+            histogram: self,
+            index: 0,
+        }
+    }
+
+    /// Get the sum of all values recorded in this histogram, saturating at `u64::MAX`.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Get the number of values recorded in this histogram.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if no values were recorded in this histogram.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Remove all recorded values.
+    pub fn clear(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            *bucket = 0;
+        }
+        self.count = 0;
+        self.sum = 0;
+    }
+
+    /// Add all values recorded in `other` to this histogram.
+    ///
+    /// Fails if the histograms keep apart a different number of significant digits.
+    pub fn merge(&mut self, other: &LogLinearHistogram) -> Result<(), HistogramError> {
+        if self.significant_digits != other.significant_digits {
+            return Err(HistogramError::LayoutMismatch);
+        }
+
+        for (bucket, &count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        Ok(())
+    }
+
+    /// Estimate the value below which `p` percent of the recorded values fall.
+    ///
+    /// See `Histogram::percentile` for how the values are estimated.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.quantile(p / 100.0)
+    }
+
+    /// Estimate the values at each of the given quantiles (`0.0..=1.0`).
+    ///
+    /// Returns `None` if no values were recorded or any quantile is NaN.
+    pub fn quantiles(&self, quantiles: &[f64]) -> Option<Vec<f64>> {
+        quantiles.iter().map(|&q| self.quantile(q)).collect()
+    }
+
+    fn quantile(&self, quantile: f64) -> Option<f64> {
+        let buckets = self
+            .buckets()
+            .map(|b| (b.start(), b.end(), b.count() as f64));
+        estimate_weighted_quantile(buckets, self.count as f64, quantile)
+    }
+}
+
+/// A bucket of a `LogLinearHistogram`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogLinearBucket {
+    start: u64,
+    end: u64,
+    count: u64,
+}
+
+impl LogLinearBucket {
+    /// The first value of this bucket.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The first value of the next bucket, or `u64::MAX` for the last bucket.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The number of values recorded in this bucket.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// An iterator over the buckets of a `LogLinearHistogram`.
+#[derive(Debug, Clone)]
+pub struct LogLinearBuckets<'a> {
+    histogram: &'a LogLinearHistogram,
+    index: usize,
+}
+
+impl<'a> Iterator for LogLinearBuckets<'a> {
+    type Item = LogLinearBucket;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let buckets = &self.histogram.buckets;
+        let bits = self.histogram.bits;
+        let count = *buckets.get(self.index)?;
+
+        let start = log_linear_start(bits, self.index);
+        let end = if self.index + 1 == buckets.len() {
+            u64::MAX
+        } else {
+            log_linear_start(bits, self.index + 1)
+        };
+        self.index += 1;
+
+        Some(LogLinearBucket { start, end, count })
+    }
+}
+
+impl fmt::Display for LogLinearHistogram {
+    /// Renders each bucket that recorded values.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .buckets()
+            .filter(|bucket| bucket.count() > 0)
+            .map(|bucket| {
+                let label = if bucket.end() == u64::MAX {
+                    format!("{} .. INF", bucket.start())
+                } else {
+                    format!("{} .. {}", bucket.start(), bucket.end())
+                };
+                (label, bucket.count())
+            })
+            .collect::<Vec<_>>();
+        write_rows(f, self.count, &rows)
+    }
+}
+
+impl Serialize for LogLinearHistogram {
+    /// Write the packed representation of `Histogram`, with a range up to `u64::MAX`.
+    ///
+    /// Only buckets that recorded values are written.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let values = self
+            .buckets()
+            .filter(|bucket| bucket.count() > 0)
+            .map(|bucket| (bucket.start().to_string(), bucket.count()))
+            .collect::<BTreeMap<String, _>>();
+
+        let mut state = serializer.serialize_struct("LogLinearHistogram", 5)?;
+        state.serialize_field("range", &[1, u64::MAX])?;
+        state.serialize_field("bucket_count", &self.bucket_count())?;
+        state.serialize_field("histogram_type", &(Type::LogLinear as u32))?;
+        state.serialize_field("values", &values)?;
+        state.serialize_field("sum", &self.sum)?;
+        state.end()
+    }
+}
+
+/// The packed representation of a log-linear `Histogram` or `LogLinearHistogram`.
+#[derive(Deserialize)]
+struct PackedLogLinear {
+    range: [u64; 2],
+    bucket_count: usize,
+    histogram_type: u32,
+    values: BTreeMap<String, u64>,
+    sum: u64,
+}
+
+impl<'de> Deserialize<'de> for LogLinearHistogram {
+    /// Rebuild a histogram from the packed representation of a log-linear histogram.
+    ///
+    /// Log-linear `Histogram`s of `u32` values share their buckets and can be restored as well.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let packed = PackedLogLinear::deserialize(deserializer)?;
+        if packed.histogram_type != Type::LogLinear as u32 {
+            return Err(de::Error::custom(format_args!(
+                "histogram type {} is not log-linear",
+                packed.histogram_type
+            )));
+        }
+
+        let value_bits = match packed.range {
+            [1, max] if max == u64::MAX => 64,
+            [1, max] if max == u64::from(u32::MAX) => 32,
+            [min, max] => {
+                return Err(de::Error::custom(format_args!(
+                    "range {}..{} does not match a log-linear histogram",
+                    min, max
+                )))
+            }
+        };
+        let digits = log_linear_digits(packed.bucket_count, value_bits).ok_or_else(|| {
+            de::Error::custom(format_args!(
+                "{} buckets do not match any log-linear histogram",
+                packed.bucket_count
+            ))
+        })?;
+
+        let mut hist = LogLinearHistogram::new(digits);
+        let mut seen = vec![false; packed.bucket_count];
+        for (start, count) in packed.values {
+            let start = start.parse::<u64>().map_err(de::Error::custom)?;
+            let idx = log_linear_index(hist.bits, start);
+            if idx >= packed.bucket_count || log_linear_start(hist.bits, idx) != start {
+                return Err(de::Error::custom(format_args!(
+                    "{} is not the start of a bucket",
+                    start
+                )));
+            }
+            if seen[idx] {
+                return Err(de::Error::custom(format_args!(
+                    "bucket {} is given more than once",
+                    start
+                )));
+            }
+            seen[idx] = true;
+            hist.buckets[idx] = count;
+            hist.count += count;
+        }
+        hist.sum = packed.sum;
+        Ok(hist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use Histogram;
+
+    #[test]
+    fn u64_values() {
+        let mut hist = LogLinearHistogram::new(2);
+        assert_eq!(7424, hist.bucket_count());
+        assert_eq!(None, hist.percentile(50.0));
+
+        let samples = (0..1000u64)
+            .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (i % 64))
+            .chain(vec![0, 1, u64::from(u32::MAX) + 1, u64::MAX]);
+        for value in samples {
+            let idx = log_linear_index(hist.bits, value);
+            let start = log_linear_start(hist.bits, idx);
+            assert!(start <= value);
+            assert!(
+                (value - start) as f64 <= value as f64 / 100.0,
+                "{} recorded in bucket starting at {}",
+                value,
+                start
+            );
+            assert!(idx + 1 == hist.bucket_count() || log_linear_start(hist.bits, idx + 1) > value);
+        }
+
+        hist.add(u64::MAX);
+        hist.accumulate(10, 3);
+        assert_eq!(4, hist.count());
+        assert_eq!(u64::MAX, hist.sum());
+        let last = hist.buckets().last().unwrap();
+        assert_eq!((u64::MAX, 1), (last.end(), last.count()));
+        assert!(last.start() > 1 << 63);
+        let p50 = hist.percentile(50.0).unwrap();
+        assert!((10.0..11.0).contains(&p50), "{}", p50);
+        assert_eq!(Some(last.start() as f64), hist.percentile(100.0));
+        assert_eq!(None, hist.percentile(f64::NAN));
+
+        let display = hist.to_string();
+        assert!(display.contains("10 .. 11 [ 3 ]"), "{}", display);
+        assert!(display.contains(" .. INF [ 1 ]"), "{}", display);
+
+        hist.clear();
+        assert!(hist.is_empty());
+        assert_eq!(0, hist.sum());
+    }
+
+    #[test]
+    fn shares_buckets_with_histogram() {
+        let mut narrow = Histogram::log_linear(1);
+        let mut wide = LogLinearHistogram::new(1);
+        for &value in &[0, 5, 31, 32, 1000, 123_456, u32::MAX] {
+            narrow.accumulate(value, 2);
+            wide.accumulate(u64::from(value), 2);
+        }
+
+        let narrow_buckets = narrow
+            .buckets()
+            .map(|b| (u64::from(b.start()), b.count()))
+            .collect::<Vec<_>>();
+        let wide_buckets = wide
+            .buckets()
+            .take(narrow.bucket_count())
+            .map(|b| (b.start(), b.count()))
+            .collect::<Vec<_>>();
+        assert_eq!(narrow_buckets, wide_buckets);
+        assert_eq!(narrow.percentile(40.0), wide.percentile(40.0));
+
+        let restored: LogLinearHistogram =
+            serde_json::from_str(&serde_json::to_string(&narrow).unwrap()).unwrap();
+        assert_eq!(wide.buckets, restored.buckets);
+        assert_eq!(wide.sum(), restored.sum());
+        assert_eq!(wide.count(), restored.count());
+    }
+
+    #[test]
+    fn serialize() {
+        let mut hist = LogLinearHistogram::new(1);
+        hist.add(3);
+        hist.accumulate(1 << 40, 2);
+
+        let serialized = serde_json::to_string(&hist).unwrap();
+        assert_eq!(
+            r#"{"range":[1,18446744073709551615],"bucket_count":976,"histogram_type":7,"values":{"1099511627776":2,"3":1},"sum":2199023255555}"#,
+            serialized
+        );
+        let restored: LogLinearHistogram = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(hist.buckets, restored.buckets);
+        assert_eq!(3, restored.count());
+        assert!(serde_json::from_str::<Histogram<Box<[u32]>>>(&serialized).is_err());
+
+        let invalid = vec![
+            r#"{"range":[1,18446744073709551615],"bucket_count":976,"histogram_type":7,"values":{"1099511627777":1},"sum":0}"#,
+            r#"{"range":[1,18446744073709551615],"bucket_count":976,"histogram_type":7,"values":{"3":1,"03":1},"sum":0}"#,
+            r#"{"range":[1,18446744073709551615],"bucket_count":977,"histogram_type":7,"values":{},"sum":0}"#,
+            r#"{"range":[1,4294967295],"bucket_count":464,"histogram_type":7,"values":{"1099511627776":1},"sum":0}"#,
+            r#"{"range":[1,500],"bucket_count":10,"histogram_type":0,"values":{},"sum":0}"#,
+        ];
+        for json in invalid {
+            assert!(
+                serde_json::from_str::<LogLinearHistogram>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = LogLinearHistogram::new(3);
+        let mut b = LogLinearHistogram::new(3);
+        a.add(5);
+        b.add(1 << 50);
+        a.merge(&b).unwrap();
+        assert_eq!(2, a.count());
+        assert_eq!(5 + (1 << 50), a.sum());
+
+        match a.merge(&LogLinearHistogram::new(2)) {
+            Err(HistogramError::LayoutMismatch) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match LogLinearHistogram::try_new(4) {
+            Err(HistogramError::InvalidPrecision { digits: 4 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Encoding histograms in the Prometheus text exposition format.
+
+use std::fmt::{self, Write};
+
+use super::{Histogram, HistogramError, KeyedHistogram};
+
+/// An encoder writing histograms as a Prometheus metric in the text exposition format.
+///
+/// Each bucket becomes a cumulative `_bucket` sample whose `le` label is the largest value it holds,
+/// one less than its end, since values are integers and bucket ends are exclusive.
+/// The last bucket, ending at `u32::MAX`, is labeled `+Inf`.
+///
+/// ```rust
+/// # use histogram::{Histogram, PrometheusEncoder};
+/// let mut hist = Histogram::linear(1, 10, 3);
+/// hist.add(3);
+/// hist.add(12);
+///
+/// let encoder = PrometheusEncoder::new("request_size_bytes")
+///     .help("Size of requests.")
+///     .label("service", "search");
+///
+/// let text = encoder.encode(&hist);
+/// assert_eq!(
+///     vec![
+///         "# HELP request_size_bytes Size of requests.",
+///         "# TYPE request_size_bytes histogram",
+///         "request_size_bytes_bucket{service=\"search\",le=\"0\"} 0",
+///         "request_size_bytes_bucket{service=\"search\",le=\"9\"} 1",
+///         "request_size_bytes_bucket{service=\"search\",le=\"+Inf\"} 2",
+///         "request_size_bytes_sum{service=\"search\"} 15",
+///         "request_size_bytes_count{service=\"search\"} 2",
+///     ],
+///     text.lines().collect::<Vec<_>>()
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrometheusEncoder {
+    name: String,
+    help: Option<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl PrometheusEncoder {
+    /// Create an encoder for the metric `name`, without help text or labels.
+    ///
+    /// The name is written as is and should be a valid Prometheus metric name.
+    pub fn new<S: Into<String>>(name: S) -> PrometheusEncoder {
+        PrometheusEncoder {
+            name: name.into(),
+            help: None,
+            labels: Vec::new(),
+        }
+    }
+
+    /// Set the help text describing the metric.
+    pub fn help<S: Into<String>>(mut self, help: S) -> PrometheusEncoder {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Add a label written on every sample of the metric.
+    ///
+    /// Labels are written in the order they are added.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the label name is invalid, see `try_label`.
+    pub fn label<K: Into<String>, V: Into<String>>(self, name: K, value: V) -> PrometheusEncoder {
+        self.try_label(name, value).expect("invalid label")
+    }
+
+    /// Add a label written on every sample of the metric.
+    ///
+    /// Labels are written in the order they are added.
+    /// Fails if the name is not a valid Prometheus label name, starts with the reserved `__`,
+    /// is `le`, or was added before.
+    pub fn try_label<K: Into<String>, V: Into<String>>(
+        mut self,
+        name: K,
+        value: V,
+    ) -> Result<PrometheusEncoder, HistogramError> {
+        let name = name.into();
+        self.check_label(&name)?;
+        self.labels.push((name, value.into()));
+        Ok(self)
+    }
+
+    /// Encode `histogram` as a single metric.
+    pub fn encode<T: AsRef<[u32]>>(&self, histogram: &Histogram<T>) -> String {
+        let mut out = String::new();
+        self.write_header(&mut out)
+            .and_then(|_| self.write_histogram(&mut out, histogram, None))
+            .expect("writing to a string can't fail");
+        out
+    }
+
+    /// Encode the histograms of all keys as a single metric,
+    /// telling them apart by a `key_label` label set to their key.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `key_label` is not a valid label name, see `try_encode_keyed`.
+    pub fn encode_keyed<T: AsRef<[u32]> + Clone>(
+        &self,
+        histogram: &KeyedHistogram<T>,
+        key_label: &str,
+    ) -> String {
+        self.try_encode_keyed(histogram, key_label)
+            .expect("invalid key label")
+    }
+
+    /// Encode the histograms of all keys as a single metric,
+    /// telling them apart by a `key_label` label set to their key.
+    ///
+    /// Fails if `key_label` could not be added with `try_label`.
+    pub fn try_encode_keyed<T: AsRef<[u32]> + Clone>(
+        &self,
+        histogram: &KeyedHistogram<T>,
+        key_label: &str,
+    ) -> Result<String, HistogramError> {
+        self.check_label(key_label)?;
+        let mut out = String::new();
+        self.write_header(&mut out)
+            .and_then(|_| {
+                for key in histogram.keys() {
+                    let hist = histogram.get(key).expect("keys are recorded");
+                    self.write_histogram(&mut out, hist, Some((key_label, key)))?;
+                }
+                Ok(())
+            })
+            .expect("writing to a string can't fail");
+        Ok(out)
+    }
+
+    /// Check that `name` is a valid label name, not reserved and not used yet.
+    fn check_label(&self, name: &str) -> Result<(), HistogramError> {
+        let mut chars = name.chars();
+        let valid = match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            _ => false,
+        };
+        if !valid
+            || name.starts_with("__")
+            || name == "le"
+            || self.labels.iter().any(|(used, _)| used == name)
+        {
+            return Err(HistogramError::InvalidLabel {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn write_header<W: Write>(&self, out: &mut W) -> fmt::Result {
+        if let Some(ref help) = self.help {
+            writeln!(out, "# HELP {} {}", self.name, escape(help, false))?;
+        }
+        writeln!(out, "# TYPE {} histogram", self.name)
+    }
+
+    fn write_histogram<W: Write, T: AsRef<[u32]>>(
+        &self,
+        out: &mut W,
+        histogram: &Histogram<T>,
+        key: Option<(&str, &str)>,
+    ) -> fmt::Result {
+        let mut cumulative = 0;
+        for bucket in histogram.buckets() {
+            cumulative += bucket.count();
+            let le = if bucket.end() == u32::MAX {
+                "+Inf".to_string()
+            } else {
+                (bucket.end() - 1).to_string()
+            };
+            write!(out, "{}_bucket", self.name)?;
+            self.write_labels(out, key, Some(&le))?;
+            writeln!(out, " {}", cumulative)?;
+        }
+
+        write!(out, "{}_sum", self.name)?;
+        self.write_labels(out, key, None)?;
+        writeln!(out, " {}", histogram.sum())?;
+        write!(out, "{}_count", self.name)?;
+        self.write_labels(out, key, None)?;
+        writeln!(out, " {}", histogram.count())
+    }
+
+    fn write_labels<W: Write>(
+        &self,
+        out: &mut W,
+        key: Option<(&str, &str)>,
+        le: Option<&str>,
+    ) -> fmt::Result {
+        let labels = self
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain(key)
+            .chain(le.map(|le| ("le", le)));
+
+        let mut separator = "{";
+        for (name, value) in labels {
+            write!(out, "{}{}=\"{}\"", separator, name, escape(value, true))?;
+            separator = ",";
+        }
+        if separator == "," {
+            out.write_char('}')?;
+        }
+        Ok(())
+    }
+}
+
+/// Escape backslashes and line feeds, and double quotes in label values.
+fn escape(text: &str, quotes: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if quotes => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let mut hist = Histogram::exponential(1, 100, 5);
+        let encoder = PrometheusEncoder::new("latency_ms");
+        let empty = encoder.encode(&hist);
+        assert!(empty.starts_with("# TYPE latency_ms histogram\nlatency_ms_bucket{le=\"0\"} 0\n"));
+        assert!(
+            empty.ends_with("latency_ms_sum 0\nlatency_ms_count 0\n"),
+            "{}",
+            empty
+        );
+
+        hist.add(0);
+        hist.add(5);
+        hist.add(50);
+        hist.add(5000);
+        let encoded = encoder.encode(&hist);
+        let counts = encoded
+            .lines()
+            .filter(|line| line.starts_with("latency_ms_bucket"))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(hist.bucket_count(), counts.len());
+        assert!(counts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(Some(&4), counts.last());
+        assert!(encoded.contains("latency_ms_bucket{le=\"+Inf\"} 4\n"));
+        assert!(encoded.ends_with("latency_ms_sum 5055\nlatency_ms_count 4\n"));
+    }
+
+    #[test]
+    fn bucket_boundaries() {
+        let mut hist = Histogram::linear(1, 10, 3);
+        hist.add(9);
+        hist.add(10);
+
+        let encoded = PrometheusEncoder::new("x").encode(&hist);
+        let buckets = encoded
+            .lines()
+            .filter(|line| line.starts_with("x_bucket"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "x_bucket{le=\"0\"} 0",
+                "x_bucket{le=\"9\"} 1",
+                "x_bucket{le=\"+Inf\"} 2",
+            ],
+            buckets
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let hist = Histogram::flag();
+        let encoded = PrometheusEncoder::new("flag")
+            .help("A \"flag\"\\\nover two lines")
+            .label("path", "C:\\dir \"a\"\nb")
+            .encode(&hist);
+        assert!(encoded.starts_with("# HELP flag A \"flag\"\\\\\\nover two lines\n"));
+        assert!(encoded.contains("flag_count{path=\"C:\\\\dir \\\"a\\\"\\nb\"} 0\n"));
+    }
+
+    #[test]
+    fn keyed() {
+        let prototype = Histogram::linear(1, 10, 3).into_shared();
+        let mut keyed = KeyedHistogram::new(prototype);
+        keyed.add("b", 3).unwrap();
+        keyed.add("a", 12).unwrap();
+        keyed.add("a", 1).unwrap();
+
+        let encoded = PrometheusEncoder::new("size")
+            .help("Sizes.")
+            .label("host", "x")
+            .encode_keyed(&keyed, "kind");
+        let expected = "# HELP size Sizes.\n\
+                        # TYPE size histogram\n\
+                        size_bucket{host=\"x\",kind=\"a\",le=\"0\"} 0\n\
+                        size_bucket{host=\"x\",kind=\"a\",le=\"9\"} 1\n\
+                        size_bucket{host=\"x\",kind=\"a\",le=\"+Inf\"} 2\n\
+                        size_sum{host=\"x\",kind=\"a\"} 13\n\
+                        size_count{host=\"x\",kind=\"a\"} 2\n\
+                        size_bucket{host=\"x\",kind=\"b\",le=\"0\"} 0\n\
+                        size_bucket{host=\"x\",kind=\"b\",le=\"9\"} 1\n\
+                        size_bucket{host=\"x\",kind=\"b\",le=\"+Inf\"} 1\n\
+                        size_sum{host=\"x\",kind=\"b\"} 3\n\
+                        size_count{host=\"x\",kind=\"b\"} 1\n";
+        assert_eq!(expected, encoded);
+    }
+
+    #[test]
+    fn invalid_labels() {
+        let encoder = PrometheusEncoder::new("size").label("host", "x");
+        for name in &["le", "host", "", "1st", "a-b", "__name__", "höst"] {
+            match encoder.clone().try_label(*name, "y") {
+                Err(HistogramError::InvalidLabel { name: ref err }) => assert_eq!(*name, err),
+                res => panic!("unexpected result for {:?}: {:?}", name, res),
+            }
+        }
+        assert!(encoder.clone().try_label("_Host2", "y").is_ok());
+
+        let keyed = KeyedHistogram::new(Histogram::linear(1, 10, 3).into_shared());
+        assert!(encoder.try_encode_keyed(&keyed, "le").is_err());
+        assert!(encoder.try_encode_keyed(&keyed, "host").is_err());
+        assert!(encoder.try_encode_keyed(&keyed, "kind").is_ok());
+    }
+}
@@ -0,0 +1,413 @@
+//! Sketches estimating quantiles with a bounded relative error, modeled after DDSketch.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use super::{write_rows, HistogramError};
+
+/// A sketch of `u64` values, answering quantile queries within a relative error.
+///
+/// Values are mapped to buckets whose bounds grow by a constant factor,
+/// so every value is within the relative accuracy of its bucket's estimate, no matter how large it is.
+/// Only buckets that recorded values are stored.
+///
+/// ```rust
+/// # use histogram::DDSketch;
+/// let mut sketch = DDSketch::new(0.01, 2048);
+///
+/// for i in 1..=1000 {
+///     sketch.add(i);
+/// }
+///
+/// let median = sketch.quantile(0.5).unwrap();
+/// assert!((median - 500.0).abs() <= 5.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DDSketch {
+    relative_accuracy: f64,
+    gamma: f64,
+    ln_gamma: f64,
+    max_buckets: u32,
+
+    zero_count: u64,
+    bins: BTreeMap<i32, u64>,
+
+    count: u64,
+    sum: u64,
+}
+
+impl DDSketch {
+    /// Create a sketch estimating values within `relative_accuracy`, using at most `max_buckets`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the parameters are invalid, see `try_new`.
+    pub fn new(relative_accuracy: f64, max_buckets: u32) -> DDSketch {
+        Self::try_new(relative_accuracy, max_buckets).expect("invalid sketch")
+    }
+
+    /// Create a sketch estimating values within `relative_accuracy`, using at most `max_buckets`.
+    ///
+    /// Zeros are counted separately and don't take up one of the buckets.
+    /// Once more buckets are needed the lowest ones are collapsed,
+    /// so only the smallest values lose accuracy.
+    /// Fails if the accuracy is not strictly between 0 and 1 or there are no buckets.
+    pub fn try_new(relative_accuracy: f64, max_buckets: u32) -> Result<DDSketch, HistogramError> {
+        if !(relative_accuracy > 0.0 && relative_accuracy < 1.0) {
+            return Err(HistogramError::InvalidAccuracy {
+                accuracy: relative_accuracy,
+            });
+        }
+        if max_buckets == 0 {
+            return Err(HistogramError::InvalidBucketCount { count: max_buckets });
+        }
+
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Ok(DDSketch {
+            relative_accuracy,
+            gamma,
+            ln_gamma: gamma.ln(),
+            max_buckets,
+            zero_count: 0,
+            bins: BTreeMap::new(),
+            count: 0,
+            sum: 0,
+        })
+    }
+
+    /// Get the relative accuracy of the estimated values.
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    /// Get the number of buckets currently in use.
+    pub fn bucket_count(&self) -> usize {
+        self.bins.len() + (self.zero_count > 0) as usize
+    }
+
+    /// Add a single value to this sketch.
+    pub fn add(&mut self, value: u64) {
+        self.accumulate(value, 1);
+    }
+
+    /// Add `value` `count` times to this sketch.
+    ///
+    /// The sum saturates at `u64::MAX`.
+    pub fn accumulate(&mut self, value: u64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        if value == 0 {
+            self.zero_count += count;
+        } else {
+            let idx = self.index(value);
+            *self.bins.entry(idx).or_insert(0) += count;
+            self.collapse();
+        }
+        self.count += count;
+        self.sum = self.sum.saturating_add(value.saturating_mul(count));
+    }
+
+    /// Get the number of values recorded in this sketch.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the sum of all values recorded in this sketch, saturating at `u64::MAX`.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Check if no values were recorded in this sketch.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Add all values recorded in `other` to this sketch.
+    ///
+    /// The buckets of both sketches line up, so merging loses no accuracy,
+    /// unless the merged sketch needs more than the maximum number of buckets.
+    /// Fails if the sketches have a different relative accuracy.
+    pub fn merge(&mut self, other: &DDSketch) -> Result<(), HistogramError> {
+        if self.relative_accuracy != other.relative_accuracy {
+            return Err(HistogramError::LayoutMismatch);
+        }
+
+        for (&idx, &count) in &other.bins {
+            *self.bins.entry(idx).or_insert(0) += count;
+        }
+        self.collapse();
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        Ok(())
+    }
+
+    /// Estimate the value at quantile `q`, between 0 and 1.
+    ///
+    /// The estimate is within the relative accuracy of a recorded value of that rank.
+    /// Returns `None` if the sketch is empty or `q` is out of range.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+
+        let rank = (q * (self.count - 1) as f64) as u64;
+        if rank < self.zero_count {
+            return Some(0.0);
+        }
+
+        let mut seen = self.zero_count;
+        self.bins
+            .iter()
+            .find(|&(_, &count)| {
+                seen += count;
+                seen > rank
+            })
+            .map(|(&idx, _)| self.value(idx))
+    }
+
+    /// Find the index of the bucket `value` falls into, the bucket from `gamma^(idx-1)` to `gamma^idx`.
+    fn index(&self, value: u64) -> i32 {
+        ((value as f64).ln() / self.ln_gamma).ceil() as i32
+    }
+
+    /// Estimate the values in the bucket at `idx`, with the same relative error to both of its bounds.
+    fn value(&self, idx: i32) -> f64 {
+        2.0 * self.gamma.powi(idx) / (self.gamma + 1.0)
+    }
+
+    /// Merge the lowest buckets into one until at most `max_buckets` remain.
+    fn collapse(&mut self) {
+        let excess = self.bins.len().saturating_sub(self.max_buckets as usize);
+        if excess == 0 {
+            return;
+        }
+
+        let collapsed = self.bins.keys().take(excess).cloned().collect::<Vec<_>>();
+        let mut count = 0;
+        for idx in collapsed {
+            count += self.bins.remove(&idx).unwrap_or(0);
+        }
+        if let Some(lowest) = self.bins.values_mut().next() {
+            *lowest += count;
+        }
+    }
+}
+
+impl fmt::Display for DDSketch {
+    /// Renders each bucket that recorded values, labeled with the smallest integer it holds.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rows = Vec::with_capacity(self.bucket_count());
+        if self.zero_count > 0 {
+            rows.push(("0".to_string(), self.zero_count));
+        }
+        for (&idx, &count) in &self.bins {
+            let start = self.gamma.powi(idx - 1).floor() as u64 + 1;
+            rows.push((start.to_string(), count));
+        }
+        write_rows(f, self.count, &rows)
+    }
+}
+
+impl Serialize for DDSketch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("DDSketch", 5)?;
+        state.serialize_field("relative_accuracy", &self.relative_accuracy)?;
+        state.serialize_field("max_buckets", &self.max_buckets)?;
+        state.serialize_field("zero_count", &self.zero_count)?;
+        state.serialize_field("bins", &self.bins)?;
+        state.serialize_field("sum", &self.sum)?;
+        state.end()
+    }
+}
+
+/// The representation as written by `impl Serialize for DDSketch`.
+#[derive(Deserialize)]
+struct PackedSketch {
+    relative_accuracy: f64,
+    max_buckets: u32,
+    zero_count: u64,
+    bins: BTreeMap<i32, u64>,
+    sum: u64,
+}
+
+impl<'de> Deserialize<'de> for DDSketch {
+    /// Rebuild a sketch from its serialized representation.
+    ///
+    /// The count is recomputed from the buckets.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let packed = PackedSketch::deserialize(deserializer)?;
+        let mut sketch = DDSketch::try_new(packed.relative_accuracy, packed.max_buckets)
+            .map_err(de::Error::custom)?;
+
+        sketch.zero_count = packed.zero_count;
+        sketch.count = packed.zero_count + packed.bins.values().sum::<u64>();
+        sketch.bins = packed.bins;
+        sketch.collapse();
+        sketch.sum = packed.sum;
+        Ok(sketch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn assert_accurate(sketch: &DDSketch, q: f64, expected: f64) {
+        let estimate = sketch.quantile(q).unwrap();
+        assert!(
+            (estimate - expected).abs() <= expected * sketch.relative_accuracy(),
+            "quantile {} is {}, expected {}",
+            q,
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let mut sketch = DDSketch::new(0.01, 2048);
+        assert_eq!(None, sketch.quantile(0.5));
+
+        // Values across many orders of magnitude.
+        let values = (0..1000u32)
+            .map(|i| 1.02f64.powi(i as i32 * 2).round() as u64)
+            .collect::<Vec<_>>();
+        for &value in &values {
+            sketch.add(value);
+        }
+
+        assert_eq!(1000, sketch.count());
+        for &q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            let rank = (q * 999.0) as usize;
+            assert_accurate(&sketch, q, values[rank] as f64);
+        }
+        assert_eq!(None, sketch.quantile(1.5));
+
+        sketch.accumulate(0, 2000);
+        assert_eq!(Some(0.0), sketch.quantile(0.5));
+        assert_accurate(&sketch, 1.0, values[999] as f64);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = DDSketch::new(0.02, 1024);
+        let mut b = DDSketch::new(0.02, 1024);
+        let mut all = DDSketch::new(0.02, 1024);
+        for i in 0..5000 {
+            let value = i * i;
+            if i % 3 == 0 {
+                a.add(value);
+            } else {
+                b.add(value);
+            }
+            all.add(value);
+        }
+
+        a.merge(&b).unwrap();
+        assert_eq!(all.count(), a.count());
+        assert_eq!(all.sum(), a.sum());
+        assert_eq!(all.bins, a.bins);
+        assert_eq!(all.zero_count, a.zero_count);
+
+        match a.merge(&DDSketch::new(0.01, 1024)) {
+            Err(HistogramError::LayoutMismatch) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn collapse() {
+        let mut sketch = DDSketch::new(0.01, 100);
+        for i in 1..=100_000 {
+            sketch.add(i);
+        }
+
+        assert_eq!(100, sketch.bucket_count());
+        assert_eq!(100_000, sketch.count());
+        assert_accurate(&sketch, 0.99, 99_000.0);
+        assert_accurate(&sketch, 1.0, 100_000.0);
+        // The lowest values are collapsed into a much larger bucket.
+        assert!(sketch.quantile(0.0).unwrap() > 1.0);
+    }
+
+    #[test]
+    fn serialize() {
+        let mut sketch = DDSketch::new(0.5, 10);
+        sketch.add(0);
+        sketch.add(1);
+        sketch.add(2);
+        sketch.add(2);
+        sketch.add(10);
+
+        let serialized = serde_json::to_string(&sketch).unwrap();
+        assert_eq!(
+            r#"{"relative_accuracy":0.5,"max_buckets":10,"zero_count":1,"bins":{"0":1,"1":2,"3":1},"sum":15}"#,
+            serialized
+        );
+
+        let restored: DDSketch = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(sketch.count(), restored.count());
+        assert_eq!(sketch.bins, restored.bins);
+        assert_eq!(sketch.quantile(0.5), restored.quantile(0.5));
+
+        let invalid =
+            r#"{"relative_accuracy":1.5,"max_buckets":10,"zero_count":0,"bins":{},"sum":0}"#;
+        assert!(serde_json::from_str::<DDSketch>(invalid).is_err());
+
+        let display = sketch.to_string();
+        assert!(
+            display.starts_with("# Number of samples = 5\n"),
+            "{}",
+            display
+        );
+        assert!(display.contains(" 0 [ 1 ]: ∎\n"), "{}", display);
+        assert!(display.contains(" 2 [ 2 ]: ∎∎\n"), "{}", display);
+        assert!(display.contains("10 [ 1 ]: ∎\n"), "{}", display);
+    }
+
+    #[test]
+    fn large_values() {
+        let mut sketch = DDSketch::new(0.01, 2048);
+        sketch.add(u64::MAX);
+        sketch.add(1);
+        assert_eq!(u64::MAX, sketch.sum());
+        assert_accurate(&sketch, 1.0, u64::MAX as f64);
+
+        let mut other = DDSketch::new(0.01, 2048);
+        other.accumulate(1 << 40, 1 << 30);
+        assert_eq!(u64::MAX, other.sum());
+
+        let mut small = DDSketch::new(0.01, 2048);
+        small.add(5);
+        small.merge(&sketch).unwrap();
+        assert_eq!(u64::MAX, small.sum());
+        assert_eq!(3, small.count());
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        for &accuracy in &[0.0, 1.0, -0.5, f64::NAN] {
+            match DDSketch::try_new(accuracy, 10) {
+                Err(HistogramError::InvalidAccuracy { .. }) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+        match DDSketch::try_new(0.01, 0) {
+            Err(HistogramError::InvalidBucketCount { count: 0 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
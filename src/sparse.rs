@@ -0,0 +1,352 @@
+//! Sparse histograms, only storing the buckets that recorded values.
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use super::{adjust_value, find_bucket, write_rows, Bucket, BucketLayout, PackedHistogram, Type};
+
+/// A histogram that only stores the buckets that recorded values.
+///
+/// Without a layout every distinct value is counted exactly in its own bucket,
+/// which suits arbitrary values like error codes or hashed identifiers.
+/// With a layout values are assigned to its buckets,
+/// recording the same data as a `Histogram` with that layout.
+///
+/// ```rust
+/// # use histogram::SparseHistogram;
+/// let mut hist = SparseHistogram::new();
+/// hist.add(0x8007_000e);
+/// hist.add(0x8007_000e);
+/// hist.add(404);
+///
+/// assert_eq!(2, hist.bucket_count());
+/// assert_eq!(3, hist.count());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SparseHistogram {
+    layout: Option<Arc<BucketLayout>>,
+    counts: BTreeMap<u32, u64>,
+
+    count: u64,
+    sum: u64,
+}
+
+impl SparseHistogram {
+    /// Create an empty histogram counting each value exactly.
+    pub fn new() -> SparseHistogram {
+        SparseHistogram::default()
+    }
+
+    /// Create an empty histogram assigning values to the buckets of the shared `layout`.
+    pub fn from_layout(layout: &Arc<BucketLayout>) -> SparseHistogram {
+        SparseHistogram {
+            layout: Some(Arc::clone(layout)),
+            ..SparseHistogram::default()
+        }
+    }
+
+    /// The layout values are assigned to, or `None` if each value is counted exactly.
+    pub fn layout(&self) -> Option<&Arc<BucketLayout>> {
+        self.layout.as_ref()
+    }
+
+    /// Get the number of buckets that recorded values.
+    pub fn bucket_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Add a single value to this histogram.
+    ///
+    /// With a layout, values are adjusted the same way as by `Histogram::add`.
+    pub fn add(&mut self, value: u32) {
+        let value = match self.layout {
+            Some(ref layout) => adjust_value(layout.kind(), value),
+            None => value,
+        };
+        self.accumulate(value, 1);
+    }
+
+    /// Add `count` number of values.
+    ///
+    /// With the layout of a count histogram the value is ignored, see `Histogram::accumulate`.
+    pub fn accumulate(&mut self, value: u32, count: u32) {
+        if count == 0 {
+            return;
+        }
+
+        let count = u64::from(count);
+        let start = match self.layout {
+            Some(ref layout) if layout.kind() == Type::Count => {
+                self.sum += count;
+                layout.ranges()[0]
+            }
+            Some(ref layout) => {
+                self.sum += u64::from(value) * count;
+                let ranges = layout.ranges();
+                ranges[find_bucket(
+                    layout.kind(),
+                    layout.min(),
+                    layout.max(),
+                    layout.bits(),
+                    ranges,
+                    value,
+                )]
+            }
+            None => {
+                self.sum += u64::from(value) * count;
+                value
+            }
+        };
+        *self.counts.entry(start).or_insert(0) += count;
+        self.count += count;
+    }
+
+    /// Get an iterator over the buckets that recorded values, in order.
+    pub fn buckets(&self) -> SparseBuckets<'_> {
+        SparseBuckets {
+            layout: self.layout.as_deref(),
+            counts: self.counts.iter(),
+        }
+    }
+
+    /// Get the sum of all values recorded in this histogram.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Get the number of values recorded in this histogram.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if no values were recorded in this histogram.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Remove all recorded values, keeping the layout.
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.count = 0;
+        self.sum = 0;
+    }
+}
+
+/// An iterator over the buckets of a `SparseHistogram` that recorded values.
+pub struct SparseBuckets<'a> {
+    layout: Option<&'a BucketLayout>,
+    counts: btree_map::Iter<'a, u32, u64>,
+}
+
+impl<'a> Iterator for SparseBuckets<'a> {
+    type Item = Bucket;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&start, &count) = self.counts.next()?;
+        let end = match self.layout {
+            Some(layout) => {
+                let ranges = layout.ranges();
+                match ranges.binary_search(&start) {
+                    Ok(idx) if idx + 1 < ranges.len() => ranges[idx + 1],
+                    _ => u32::MAX,
+                }
+            }
+            None => start.saturating_add(1),
+        };
+
+        Some(Bucket { start, end, count })
+    }
+}
+
+impl fmt::Display for SparseHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .buckets()
+            .map(|bucket| {
+                let label = match self.layout {
+                    Some(_) if bucket.end() == u32::MAX => format!("{} .. INF", bucket.start()),
+                    Some(_) => format!("{} .. {}", bucket.start(), bucket.end()),
+                    None => bucket.start().to_string(),
+                };
+                (label, bucket.count())
+            })
+            .collect::<Vec<_>>();
+        write_rows(f, self.count, &rows)
+    }
+}
+
+impl Serialize for SparseHistogram {
+    /// Write the packed representation of `Histogram`.
+    ///
+    /// With a layout the output matches that of a `Histogram` with the same layout and data.
+    /// Without one, the histogram is of type `Sparse` and its values are the recorded values.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut values = self
+            .counts
+            .iter()
+            .map(|(start, &count)| (start.to_string(), count))
+            .collect::<BTreeMap<String, _>>();
+
+        let mut state = serializer.serialize_struct("SparseHistogram", 5)?;
+        match self.layout {
+            Some(ref layout) => {
+                // Mark the buckets around the recorded ones as empty, like `Histogram` does.
+                let ranges = layout.ranges();
+                if let Some(&first) = self.counts.keys().next() {
+                    if first != ranges[0] {
+                        values.insert("0".to_string(), 0);
+                    }
+                }
+                if let Some(last) = self.buckets().last() {
+                    if last.end() != u32::MAX {
+                        values.insert(last.end().to_string(), 0);
+                    }
+                }
+
+                state.serialize_field("range", &[layout.min(), layout.max()])?;
+                state.serialize_field("bucket_count", &layout.bucket_count())?;
+                state.serialize_field("histogram_type", &(layout.kind() as u32))?;
+            }
+            None => {
+                state.serialize_field("range", &[0, u32::MAX])?;
+                state.serialize_field("bucket_count", &self.counts.len())?;
+                state.serialize_field("histogram_type", &(Type::Sparse as u32))?;
+            }
+        }
+        state.serialize_field("values", &values)?;
+        state.serialize_field("sum", &self.sum)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SparseHistogram {
+    /// Rebuild a sparse histogram from the packed representation of `Histogram`.
+    ///
+    /// Histograms of other types than `Sparse` are restored with their layout,
+    /// so the same types as for `Histogram` are supported.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let packed = PackedHistogram::deserialize(deserializer)?;
+        let mut hist = SparseHistogram::new();
+
+        if packed.histogram_type != Type::Sparse as u32 {
+            let dense = packed.unpack()?;
+            hist.layout = Some(Arc::new(dense.layout()));
+            for bucket in dense.buckets().filter(|bucket| bucket.count() > 0) {
+                hist.counts.insert(bucket.start(), bucket.count());
+            }
+            hist.count = dense.count();
+            hist.sum = dense.sum();
+            return Ok(hist);
+        }
+
+        for (value, &count) in packed.values.iter().filter(|&(_, &count)| count > 0) {
+            let value = value.parse::<u32>().map_err(de::Error::custom)?;
+            if hist.counts.insert(value, count).is_some() {
+                return Err(de::Error::custom(format_args!(
+                    "value {} is given more than once",
+                    value
+                )));
+            }
+            hist.count += count;
+        }
+        hist.sum = packed.sum;
+        Ok(hist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use Histogram;
+
+    #[test]
+    fn exact() {
+        let mut hist = SparseHistogram::new();
+        hist.add(u32::MAX);
+        hist.accumulate(7, 3);
+        hist.add(0);
+        hist.accumulate(1, 0);
+
+        assert_eq!(3, hist.bucket_count());
+        assert_eq!(5, hist.count());
+        assert_eq!(u64::from(u32::MAX) + 21, hist.sum());
+
+        let buckets = hist
+            .buckets()
+            .map(|b| (b.start(), b.end(), b.count()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(0, 1, 1), (7, 8, 3), (u32::MAX, u32::MAX, 1)], buckets);
+
+        let serialized = serde_json::to_string(&hist).unwrap();
+        assert_eq!(
+            r#"{"range":[0,4294967295],"bucket_count":3,"histogram_type":8,"values":{"0":1,"4294967295":1,"7":3},"sum":4294967316}"#,
+            serialized
+        );
+        let restored: SparseHistogram = serde_json::from_str(&serialized).unwrap();
+        assert!(restored.layout().is_none());
+        assert_eq!(hist.counts, restored.counts);
+        assert_eq!(hist.count(), restored.count());
+        assert!(serde_json::from_str::<Histogram<Box<[u32]>>>(&serialized).is_err());
+        let duplicate = r#"{"range":[0,4294967295],"bucket_count":2,"histogram_type":8,"values":{"7":3,"07":1},"sum":28}"#;
+        assert!(serde_json::from_str::<SparseHistogram>(duplicate).is_err());
+
+        let display = hist.to_string();
+        assert!(display.contains("         7 [ 3 ]: ∎∎∎\n"), "{}", display);
+
+        hist.clear();
+        assert!(hist.is_empty());
+        assert_eq!(0, hist.buckets().count());
+    }
+
+    #[test]
+    fn with_layout() {
+        let layouts = vec![
+            Histogram::exponential(1, 500, 10),
+            Histogram::linear(1, 100, 20),
+            Histogram::count_histogram(),
+        ];
+
+        for dense in layouts {
+            let mut dense = dense;
+            let layout = Arc::new(dense.layout());
+            let mut sparse = SparseHistogram::from_layout(&layout);
+            for &value in &[3, 3, 17, 60, 61, 499, u32::MAX] {
+                dense.add(value);
+                sparse.add(value);
+            }
+            sparse.accumulate(42, 5);
+            dense.accumulate(42, 5);
+
+            assert_eq!(dense.count(), sparse.count());
+            assert_eq!(dense.sum(), sparse.sum());
+            let dense_buckets = dense
+                .buckets()
+                .filter(|b| b.count() > 0)
+                .map(|b| (b.start(), b.end(), b.count()))
+                .collect::<Vec<_>>();
+            let sparse_buckets = sparse
+                .buckets()
+                .map(|b| (b.start(), b.end(), b.count()))
+                .collect::<Vec<_>>();
+            assert_eq!(dense_buckets, sparse_buckets);
+
+            let serialized = serde_json::to_string(&sparse).unwrap();
+            assert_eq!(serde_json::to_string(&dense).unwrap(), serialized);
+            let restored: SparseHistogram = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(Some(&layout), restored.layout());
+            assert_eq!(sparse.counts, restored.counts);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Histograms over a sliding window of time.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{BucketLayout, Histogram, SharedLayout, Snapshot};
+
+/// A source of the current time.
+///
+/// Implement this to control how a `WindowedHistogram` moves its window,
+/// for example to make tests independent of the actual time.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A histogram of the values recorded within the last `window` of time.
+///
+/// The window is split into equally long slots, each recording into its own histogram.
+/// Once a slot is older than the window its values are dropped,
+/// so the window moves in steps of one slot.
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # use histogram::{Histogram, WindowedHistogram};
+/// let layout = Arc::new(Histogram::exponential(1, 10_000, 50).layout());
+/// let mut latency = WindowedHistogram::new(&layout, Duration::from_secs(300), 10);
+///
+/// latency.add(42);
+/// latency.add(180);
+///
+/// assert_eq!(2, latency.histogram().count());
+/// ```
+#[derive(Debug)]
+pub struct WindowedHistogram<C: Clock = SystemClock> {
+    slots: Box<[Slot]>,
+    slot_duration: Duration,
+    epoch: Instant,
+    clock: C,
+}
+
+#[derive(Debug)]
+struct Slot {
+    /// The number of slot durations since the epoch this slot records values for.
+    period: u64,
+    histogram: Histogram<SharedLayout>,
+}
+
+impl WindowedHistogram<SystemClock> {
+    /// Create a histogram of the last `window` of time, moving in `slots` steps.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if there are no slots or the window is shorter than one nanosecond per slot.
+    pub fn new(
+        layout: &Arc<BucketLayout>,
+        window: Duration,
+        slots: u32,
+    ) -> WindowedHistogram<SystemClock> {
+        WindowedHistogram::with_clock(layout, window, slots, SystemClock)
+    }
+}
+
+impl<C: Clock> WindowedHistogram<C> {
+    /// Create a histogram of the last `window` of time as told by `clock`, moving in `slots` steps.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if there are no slots or the window is shorter than one nanosecond per slot.
+    pub fn with_clock(
+        layout: &Arc<BucketLayout>,
+        window: Duration,
+        slots: u32,
+        clock: C,
+    ) -> WindowedHistogram<C> {
+        assert!(slots > 0, "a windowed histogram needs at least one slot");
+        let slot_duration = window / slots;
+        assert!(
+            slot_duration > Duration::from_nanos(0),
+            "the window is too short for {} slots",
+            slots
+        );
+
+        let slots = (0..slots)
+            .map(|_| Slot {
+                period: 0,
+                histogram: Histogram::from_layout(layout),
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        let epoch = clock.now();
+
+        WindowedHistogram {
+            slots,
+            slot_duration,
+            epoch,
+            clock,
+        }
+    }
+
+    /// Add a single value to the current slot.
+    pub fn add(&mut self, value: u32) {
+        self.current_slot().add(value);
+    }
+
+    /// Add `count` number of values to the current slot.
+    pub fn accumulate(&mut self, value: u32, count: u32) {
+        self.current_slot().accumulate(value, count);
+    }
+
+    /// Get a histogram of all values recorded within the window.
+    pub fn histogram(&self) -> Histogram<SharedLayout> {
+        let period = self.period();
+        let len = self.slots.len() as u64;
+
+        let mut histogram = self.slots[0].histogram.empty_like();
+        for slot in self.slots.iter() {
+            if slot.period <= period && slot.period + len > period {
+                histogram
+                    .merge(&slot.histogram)
+                    .expect("all slots share the same layout");
+            }
+        }
+        histogram
+    }
+
+    /// Estimate the value below which `p` percent of the values within the window fall.
+    ///
+    /// See `Histogram::percentile`.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.histogram().percentile(p)
+    }
+
+    /// Get a copy of the data recorded within the window.
+    pub fn snapshot(&self) -> Snapshot {
+        self.histogram().snapshot()
+    }
+
+    /// The number of slot durations since the epoch.
+    fn period(&self) -> u64 {
+        let elapsed = self.clock.now().saturating_duration_since(self.epoch);
+        (elapsed.as_nanos() / self.slot_duration.as_nanos()) as u64
+    }
+
+    /// Get the histogram of the current slot, dropping the values it recorded a window ago.
+    fn current_slot(&mut self) -> &mut Histogram<SharedLayout> {
+        let period = self.period();
+        let idx = (period % self.slots.len() as u64) as usize;
+
+        let slot = &mut self.slots[idx];
+        if slot.period != period {
+            slot.period = period;
+            slot.histogram.clear();
+        }
+        &mut slot.histogram
+    }
+}
+
+impl<C: Clock> fmt::Display for WindowedHistogram<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.histogram().fmt(f)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A clock that only moves when told to, shared by all of its clones.
+    #[derive(Clone)]
+    pub struct ManualClock(Rc<Cell<Instant>>);
+
+    impl Default for ManualClock {
+        fn default() -> ManualClock {
+            ManualClock(Rc::new(Cell::new(Instant::now())))
+        }
+    }
+
+    impl ManualClock {
+        pub fn advance(&self, secs: u64) {
+            self.0.set(self.0.get() + Duration::from_secs(secs));
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn window() {
+        let layout = Arc::new(Histogram::linear(1, 100, 11).layout());
+        let clock = ManualClock::default();
+        let mut hist =
+            WindowedHistogram::with_clock(&layout, Duration::from_secs(60), 6, clock.clone());
+
+        hist.add(5);
+        clock.advance(30);
+        hist.accumulate(50, 2);
+        assert_eq!(3, hist.histogram().count());
+        assert_eq!(105, hist.histogram().sum());
+
+        // The slot of the first value falls out of the window, the second slot is still in.
+        clock.advance(35);
+        assert_eq!(2, hist.histogram().count());
+        hist.add(90);
+        assert_eq!(3, hist.histogram().count());
+        let p50 = hist.percentile(50.0).unwrap();
+        assert!((45.0..56.0).contains(&p50), "{}", p50);
+        assert_eq!(Some(p50), hist.snapshot().percentile(50.0));
+
+        let display = hist.to_string();
+        assert!(
+            display.starts_with("# Number of samples = 3\n"),
+            "{}",
+            display
+        );
+
+        // Slots are reused after a full window.
+        clock.advance(600);
+        assert!(hist.histogram().is_empty());
+        hist.add(5);
+        assert_eq!(1, hist.histogram().count());
+        assert_eq!(5, hist.histogram().sum());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one slot")]
+    fn no_slots() {
+        let layout = Arc::new(Histogram::linear(1, 100, 11).layout());
+        WindowedHistogram::new(&layout, Duration::from_secs(60), 0);
+    }
+}